@@ -0,0 +1,56 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use colored::*;
+
+use mag_lang::runtime::{Runtime, RuntimeConfig, Source};
+
+/// The extension of bytecode files produced by `mag build`.
+pub const BYTECODE_EXTENSION: &str = "magb";
+
+/// Compile a source file to a bytecode file, returning false if it couldn't be
+/// compiled or written.
+pub fn build(path: &str, output: Option<&str>, config: RuntimeConfig) -> bool {
+    let text = match super::read_source(path) {
+        Some(text) => text,
+        None => return false,
+    };
+
+    let mut runtime = match Runtime::new(config) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+            return false;
+        }
+    };
+
+    let bytecode = match runtime.build(Source::new(path, text)) {
+        Ok(bytecode) => bytecode,
+        Err(e) => {
+            eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+            return false;
+        }
+    };
+
+    let output = output
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(path).with_extension(BYTECODE_EXTENSION));
+
+    let result = File::create(&output)
+        .map_err(Into::into)
+        .and_then(|file| bytecode.write(BufWriter::new(file)));
+
+    match result {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!(
+                "{} failed to write '{}': {}",
+                "error:".bright_red().bold(),
+                output.display(),
+                e
+            );
+            false
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use std::fs;
+
+use clap::ArgEnum;
+use colored::*;
+use serde::Serialize;
+
+use mag_lang::runtime::{Diagnostic, Runtime, RuntimeConfig, Source};
+
+/// How `mag check` reports its results.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Annotated diagnostics for people reading a terminal.
+    Human,
+    /// A machine-readable report on standard output.
+    Json,
+}
+
+#[derive(Serialize)]
+struct FileReport {
+    file: String,
+    ok: bool,
+    diagnostics: Vec<DiagnosticReport>,
+}
+
+#[derive(Serialize)]
+struct DiagnosticReport {
+    message: String,
+    labels: Vec<LabelReport>,
+    help: Vec<String>,
+}
+
+/// A labelled span with one-based line and column numbers.
+#[derive(Serialize)]
+struct LabelReport {
+    message: String,
+    file: String,
+    line: usize,
+    column: usize,
+    end_line: usize,
+    end_column: usize,
+}
+
+impl From<&Diagnostic> for DiagnosticReport {
+    fn from(diagnostic: &Diagnostic) -> Self {
+        Self {
+            message: diagnostic.message.clone(),
+            labels: diagnostic
+                .labels
+                .iter()
+                .map(|label| {
+                    let (line, column) = label.location.line_column();
+                    let (end_line, end_column) = label.location.end_line_column();
+
+                    LabelReport {
+                        message: label.message.clone(),
+                        file: label.location.source.name.clone(),
+                        line: line + 1,
+                        column: column + 1,
+                        end_line: end_line + 1,
+                        end_column: end_column + 1,
+                    }
+                })
+                .collect(),
+            help: diagnostic.help.clone(),
+        }
+    }
+}
+
+/// Lex, parse and compile each file without executing it, returning true if all of
+/// them compiled successfully.
+pub fn check(files: &[String], format: OutputFormat, config: RuntimeConfig) -> bool {
+    let mut reports = vec![];
+
+    for file in files {
+        let diagnostics = check_file(file, config.clone());
+
+        if format == OutputFormat::Human {
+            for diagnostic in &diagnostics {
+                eprint!("{}", diagnostic.render(super::DIAGNOSTIC_CONTEXT));
+            }
+        }
+
+        reports.push(FileReport {
+            file: file.clone(),
+            ok: diagnostics.is_empty(),
+            diagnostics: diagnostics.iter().map(DiagnosticReport::from).collect(),
+        });
+    }
+
+    let failed = reports.iter().filter(|report| !report.ok).count();
+
+    match format {
+        OutputFormat::Human => {
+            let summary = format!(
+                "checked {} file{}, {} failed",
+                files.len(),
+                if files.len() == 1 { "" } else { "s" },
+                failed
+            );
+
+            if failed == 0 {
+                eprintln!("{} {}", "ok:".bright_green().bold(), summary);
+            } else {
+                eprintln!("{} {}", "error:".bright_red().bold(), summary);
+            }
+        }
+        OutputFormat::Json => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&reports).expect("failed to serialize report")
+            );
+        }
+    }
+
+    failed == 0
+}
+
+fn check_file(path: &str, config: RuntimeConfig) -> Vec<Diagnostic> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => return vec![Diagnostic::new(super::read_error(path, e))],
+    };
+
+    let mut runtime = match Runtime::new(config) {
+        Ok(runtime) => runtime,
+        Err(e) => return vec![e.to_diagnostic()],
+    };
+
+    runtime
+        .check(Source::new(path, text))
+        .iter()
+        .map(|e| e.to_diagnostic())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn config() -> RuntimeConfig {
+        RuntimeConfig {
+            prelude: false,
+            ..RuntimeConfig::default()
+        }
+    }
+
+    /// Write a source file to a directory of its own, which is removed by the caller.
+    fn write_file(name: &str, text: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("mag-check-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(format!("{}.mag", name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn accepts_valid_files() {
+        let path = write_file("valid", "def double(n Int) n * 2\ndouble(4)\n");
+        let file = path.display().to_string();
+
+        let diagnostics = check_file(&file, config());
+        let ok = check(&[file], OutputFormat::Json, config());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert!(diagnostics.is_empty());
+        assert!(ok);
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_positions() {
+        let path = write_file("invalid", "1 + 2\n3 + )\n");
+        let file = path.display().to_string();
+
+        let diagnostics = check_file(&file, config());
+        let ok = check(&[file.clone()], OutputFormat::Json, config());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+
+        assert!(!ok);
+        assert_eq!(diagnostics.len(), 1);
+
+        let report = DiagnosticReport::from(&diagnostics[0]);
+        assert_eq!(report.labels[0].file, file);
+        assert_eq!((report.labels[0].line, report.labels[0].column), (2, 5));
+    }
+
+    #[test]
+    fn reports_unreadable_files() {
+        let diagnostics = check_file("does/not/exist.mag", config());
+
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].labels.is_empty());
+    }
+}
@@ -0,0 +1,29 @@
+use mag_lang::runtime::{Runtime, RuntimeConfig, Source};
+
+/// Compile a file and print a listing of its instructions, returning false if the file
+/// couldn't be read or compiled.
+pub fn disasm(path: &str, config: RuntimeConfig) -> bool {
+    let text = match super::read_source(path) {
+        Some(text) => text,
+        None => return false,
+    };
+
+    let mut runtime = match Runtime::new(config) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+            return false;
+        }
+    };
+
+    match runtime.disassemble_source(Source::new(path, text)) {
+        Ok(listing) => {
+            print!("{}", listing);
+            true
+        }
+        Err(e) => {
+            eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+            false
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! Implementations of the `mag` subcommands.
+
+pub mod build;
+pub mod check;
+pub mod disasm;
+pub mod run;
+
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Write};
+
+use colored::*;
+use mag_lang::runtime::{format_pattern, DebugEvent, Runtime, RuntimeConfig, RuntimeError};
+
+/// The number of lines shown above an error when reporting diagnostics for a script.
+pub const DIAGNOSTIC_CONTEXT: usize = 2;
+
+/// The message shown when a file can't be read.
+pub fn read_error(path: &str, error: impl Display) -> String {
+    format!("failed to read file '{}': {}", path, error)
+}
+
+/// Print an error for a file that couldn't be read.
+pub fn report_read_error(path: &str, error: impl Display) {
+    eprintln!(
+        "{} {}",
+        "error:".bright_red().bold(),
+        read_error(path, error)
+    );
+}
+
+/// Read a source file, reporting an error and returning `None` if it can't be read.
+pub fn read_source(path: &str) -> Option<String> {
+    fs::read_to_string(path)
+        .map_err(|e| report_read_error(path, e))
+        .ok()
+}
+
+/// Create a runtime which prints the output of scripts and its debug output to the
+/// terminal.
+pub fn new_runtime(config: RuntimeConfig) -> Result<Runtime, RuntimeError> {
+    let mut runtime = Runtime::new(config)?;
+
+    runtime.on_output(|text| {
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    });
+    runtime.on_debug(|event| match event {
+        DebugEvent::Compiled { listing } => {
+            println!("{}\n{}", "instructions:".bright_blue().bold(), listing);
+        }
+        DebugEvent::MethodRegistered { method } => {
+            println!(
+                "Registered method {} with pattern {} at address {}",
+                method.name,
+                format_pattern(&method.pattern),
+                method.address
+            );
+        }
+    });
+
+    Ok(runtime)
+}
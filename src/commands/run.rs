@@ -0,0 +1,128 @@
+use std::fs;
+use std::io::{self, Read};
+
+use colored::*;
+use mag_lang::runtime::{
+    BytecodeFile, CancellationToken, RunOutcome, Runtime, RuntimeConfig, Source, Value,
+};
+use signal_hook::{consts::SIGINT, flag};
+
+/// Execute a source or bytecode file with the given script arguments and return its
+/// result, exiting the process if the file can't be read or fails to run.
+pub fn run_file(path: &str, args: &[String], config: RuntimeConfig) -> Value {
+    let bytes = fs::read(path).unwrap_or_else(|e| {
+        super::report_read_error(path, e);
+        std::process::exit(1);
+    });
+
+    if BytecodeFile::is_bytecode(&bytes) {
+        return run_bytecode(&bytes, path, args, config);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(source) => run_source(source, path, args, config),
+        Err(e) => {
+            super::report_read_error(path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn run_stdin(args: &[String], config: RuntimeConfig) -> Value {
+    let mut source = String::new();
+    io::stdin()
+        .read_to_string(&mut source)
+        .expect("failed to read stdin");
+    run_source(source, "<stdin>", args, config)
+}
+
+/// Derive the exit status of `mag run` from the value a script returned: integers from
+/// 0 to 255 are used as they are, `false` becomes 1 and everything else, including
+/// integers outside that range, counts as success.
+pub fn exit_code(value: &Value) -> i32 {
+    match value {
+        Value::Int(code) => u8::try_from(*code).map_or(0, i32::from),
+        Value::UInt(code) => u8::try_from(*code).map_or(0, i32::from),
+        Value::Bool(false) => 1,
+        _ => 0,
+    }
+}
+
+fn run_source(source: String, name: &str, args: &[String], config: RuntimeConfig) -> Value {
+    let debug = config.debug;
+    let mut runtime = prepare_runtime(args, config);
+    let cancellation = interrupt_token();
+
+    let outcome = runtime.run(Source::new(name, source), &cancellation);
+    report(outcome, debug)
+}
+
+fn run_bytecode(bytes: &[u8], path: &str, args: &[String], config: RuntimeConfig) -> Value {
+    let bytecode = BytecodeFile::read(bytes).unwrap_or_else(|e| {
+        eprintln!(
+            "{} failed to load '{}': {}",
+            "error:".bright_red().bold(),
+            path,
+            e
+        );
+        std::process::exit(1);
+    });
+
+    let debug = config.debug;
+    let mut runtime = prepare_runtime(args, config);
+    let cancellation = interrupt_token();
+
+    let outcome = runtime.run_bytecode(bytecode, &cancellation);
+    report(outcome, debug)
+}
+
+/// Create a runtime for a script with the given arguments, exiting the process if it
+/// can't be set up.
+fn prepare_runtime(args: &[String], config: RuntimeConfig) -> Runtime {
+    let runtime = super::new_runtime(config).and_then(|mut runtime| {
+        runtime.set_args(args)?;
+        Ok(runtime)
+    });
+
+    runtime.unwrap_or_else(|e| {
+        eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+        std::process::exit(1);
+    })
+}
+
+/// Create a cancellation token which is triggered by Ctrl-C.
+fn interrupt_token() -> CancellationToken {
+    let cancellation = CancellationToken::new();
+
+    if let Err(e) = flag::register(SIGINT, cancellation.flag()) {
+        eprintln!(
+            "{} failed to register interrupt handler: {}",
+            "warning:".bright_yellow().bold(),
+            e
+        );
+    }
+
+    cancellation
+}
+
+/// Report the outcome of a run, exiting the process unless it finished successfully.
+fn report(outcome: RunOutcome, debug: bool) -> Value {
+    match outcome {
+        RunOutcome::Value(result) => {
+            // Print result from ret register if present
+            if debug && !result.is_empty() {
+                println!("{}", result);
+            }
+
+            result
+        }
+        RunOutcome::Error(e) => {
+            eprint!("{}", e.to_diagnostic().render(super::DIAGNOSTIC_CONTEXT));
+            std::process::exit(1);
+        }
+        RunOutcome::Interrupted => {
+            eprintln!("{}", "interrupted".bright_yellow().bold());
+            std::process::exit(130);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mag_lang::RuntimeConfig;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The name of the configuration file inside the configuration directory.
+const CONFIG_FILE: &str = "config.toml";
+
+/// The settings read from `config.toml`, which the command line flags override:
+///
+/// ```toml
+/// debug = false
+/// prelude = true
+///
+/// [repl]
+/// prompt = "mag> "
+/// theme = "ocean"
+/// history_size = 5000
+/// history_path = "~/.local/share/mag/history"
+///
+/// [limits]
+/// timeout = 30
+/// ```
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Enable debug output by default.
+    pub debug: bool,
+    /// Load the standard library prelude into every runtime.
+    pub prelude: bool,
+    pub repl: ReplConfig,
+    pub limits: Limits,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplConfig {
+    /// The prompt shown in front of the first line of the input.
+    pub prompt: String,
+    /// The prompt shown in front of every further line of a multi-line input.
+    pub continuation_prompt: String,
+    /// The name of the theme, either built in or a file in the themes directory.
+    pub theme: String,
+    /// The most history entries kept, dropping the oldest ones first.
+    pub history_size: usize,
+    /// The file the history is saved to. An empty path keeps the history only for the
+    /// session.
+    #[serde(with = "optional_path")]
+    pub history_path: Option<PathBuf>,
+}
+
+/// Limits on the work a single run may do in the virtual machine. The time limit is the
+/// only one so far, since the machine has no other limit a runtime could configure.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// The most seconds a run may take before it is stopped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+}
+
+/// An error which occurred while loading the configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "failed to read '{}': {}", path.display(), e),
+            Self::Invalid(path, e) => {
+                write!(f, "invalid configuration '{}': {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            prelude: true,
+            repl: ReplConfig::default(),
+            limits: Limits::default(),
+        }
+    }
+}
+
+impl Default for ReplConfig {
+    fn default() -> Self {
+        Self {
+            prompt: ">>> ".to_string(),
+            continuation_prompt: "... ".to_string(),
+            theme: "mag".to_string(),
+            history_size: 1000,
+            history_path: home_dir().map(|home| home.join(".mag_history")),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from the given file, or from `config.toml` in the
+    /// configuration directory if it exists. Environment variables like
+    /// `MAG_REPL_THEME` take precedence over the file.
+    ///
+    /// Returns the configuration together with the file it was read from, if any.
+    pub fn load(path: Option<&Path>) -> Result<(Self, Option<PathBuf>), ConfigError> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => config_dir()
+                .map(|directory| directory.join(CONFIG_FILE))
+                .filter(|path| path.is_file()),
+        };
+
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if let Ok(theme) = env::var("MAG_REPL_THEME") {
+            config.repl.theme = theme;
+        }
+
+        Ok((config, path))
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        let mut config: Self = toml::from_str(&text)
+            .map_err(|e| ConfigError::Invalid(path.to_path_buf(), e.to_string()))?;
+
+        if let Some(timeout) = config.limits.timeout {
+            timeout_duration(timeout).map_err(|reason| {
+                ConfigError::Invalid(path.to_path_buf(), format!("`limits.timeout` {}", reason))
+            })?;
+        }
+
+        config.repl.history_path = config.repl.history_path.map(|path| expand_home(&path));
+        Ok(config)
+    }
+
+    /// The configuration of the runtimes created by the command line.
+    pub fn runtime_config(&self) -> RuntimeConfig {
+        RuntimeConfig {
+            debug: self.debug,
+            prelude: self.prelude,
+            // Loading the configuration rejects timeouts which aren't a valid duration.
+            timeout: self
+                .limits
+                .timeout
+                .and_then(|timeout| timeout_duration(timeout).ok()),
+            ..RuntimeConfig::default()
+        }
+    }
+
+    /// Format the configuration the way it would be written in `config.toml`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("configuration should serialize to TOML")
+    }
+}
+
+/// Convert a time limit in seconds to a duration, explaining why it isn't one if it's
+/// zero, negative, not a number or too long to represent.
+pub fn timeout_duration(seconds: f64) -> Result<Duration, &'static str> {
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err("must be a positive number of seconds");
+    }
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| "is too long")
+}
+
+/// The directory holding the configuration file and themes, following the XDG base
+/// directory spec.
+pub fn config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+        .map(|config| config.join("mag"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Paths which can be left out on purpose, written as an empty string since TOML has no
+/// value for nothing.
+mod optional_path {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => path.serialize(serializer),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        let path = PathBuf::deserialize(deserializer)?;
+        Ok(Some(path).filter(|path| !path.as_os_str().is_empty()))
+    }
+}
+
+/// Replace a leading `~` in a path with the home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Load a configuration file with the given contents, removing it again afterwards.
+    fn load(name: &str, text: &str) -> Result<Config, ConfigError> {
+        let path = env::temp_dir().join(format!("mag-config-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, text).unwrap();
+        let result = Config::load(Some(&path));
+        fs::remove_file(&path).unwrap();
+
+        result.map(|(config, loaded)| {
+            assert_eq!(loaded, Some(path));
+            config
+        })
+    }
+
+    #[test]
+    fn keeps_defaults_for_missing_settings() {
+        let text = r#"
+prelude = false
+
+[repl]
+prompt = "> "
+history_path = ""
+
+[limits]
+timeout = 2.5
+"#;
+        let config = load("partial", text).unwrap();
+
+        assert!(!config.debug);
+        assert!(!config.prelude);
+        assert_eq!(config.repl.prompt, "> ");
+        assert_eq!(config.repl.continuation_prompt, "... ");
+        assert_eq!(config.repl.history_size, 1000);
+        assert_eq!(config.repl.history_path, None);
+
+        let runtime = config.runtime_config();
+        assert!(!runtime.prelude);
+        assert_eq!(runtime.timeout, Some(Duration::from_millis(2500)));
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        for (name, text) in [
+            ("unknown", "colour = true"),
+            ("type", "debug = \"yes\""),
+            ("timeout", "[limits]\ntimeout = -1"),
+            ("huge-timeout", "[limits]\ntimeout = 1e300"),
+        ] {
+            assert!(
+                matches!(load(name, text), Err(ConfigError::Invalid(..))),
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn reports_missing_files() {
+        let result = Config::load(Some(Path::new("does/not/exist.toml")));
+
+        assert!(matches!(result, Err(ConfigError::Io(..))));
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let mut config = Config::default();
+        config.repl.history_path = None;
+        config.limits.timeout = Some(3.0);
+
+        let read: Config = toml::from_str(&config.to_toml()).unwrap();
+        assert_eq!(read.repl.history_path, None);
+        assert_eq!(read.limits.timeout, Some(3.0));
+        assert_eq!(read.repl.theme, config.repl.theme);
+    }
+}
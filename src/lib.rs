@@ -0,0 +1,23 @@
+//! The Mag language runtime.
+//!
+//! This crate compiles Mag source code with `magc` and executes it on the `strontium`
+//! virtual machine. Besides powering the `mag` command line tool, the [`Runtime`] can
+//! be embedded in other Rust applications to use Mag as a scripting language:
+//!
+//! ```no_run
+//! use mag_lang::{Runtime, RuntimeConfig};
+//!
+//! let mut runtime = Runtime::new(RuntimeConfig::default()).unwrap();
+//!
+//! runtime.eval("def square(n Int) n * n").unwrap();
+//! let result: i64 = runtime.eval("square(12)").unwrap().try_into().unwrap();
+//!
+//! assert_eq!(result, 144);
+//! ```
+//!
+//! Errors are returned as a [`RuntimeError`], which can be turned into a
+//! [`Diagnostic`](runtime::Diagnostic) pointing at the offending source code.
+
+pub mod runtime;
+
+pub use runtime::{RunOutcome, Runtime, RuntimeConfig, RuntimeError, Value};
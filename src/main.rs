@@ -1,138 +1,257 @@
-pub mod repl;
-pub mod runtime;
+mod commands;
+mod config;
+mod repl;
 
-use std::fs;
-use std::io::{self, IsTerminal, Read};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::*;
+use commands::check::OutputFormat;
+use config::{Config, ReplConfig};
+use mag_lang::RuntimeConfig;
 use repl::Repl;
-use runtime::{Runtime, RuntimeConfig};
-
-use strontium::machine::bytecode::BytecodeError;
-use strontium::types::StrontiumError;
 
 #[derive(Parser)]
-#[clap(name = "mag", about = "The Mag Language Runtime")]
+#[clap(
+    name = "mag",
+    about = "The Mag Language Runtime",
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Source file to execute
     file: Option<String>,
 
     /// Enable debug output
-    #[clap(long)]
+    #[clap(long, global = true)]
     debug: bool,
+
+    /// Disable debug output, even if the configuration enables it
+    #[clap(long, global = true, conflicts_with = "debug")]
+    no_debug: bool,
+
+    /// Load the standard library prelude, even if the configuration disables it
+    #[clap(long, global = true)]
+    prelude: bool,
+
+    /// Start without the standard library prelude
+    #[clap(long, global = true, conflicts_with = "prelude")]
+    no_prelude: bool,
+
+    /// Stop runs which take longer than this many seconds
+    #[clap(long, global = true, value_name = "SECONDS")]
+    timeout: Option<f64>,
+
+    /// Let runs take as long as they need, even if the configuration sets a time limit
+    #[clap(long, global = true, conflicts_with = "timeout")]
+    no_timeout: bool,
+
+    /// Read the configuration from this file instead of `~/.config/mag/config.toml`
+    #[clap(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Execute a source or bytecode file, or standard input if no file is given
+    ///
+    /// Arguments after `--` are passed to the script through `args()`, and the exit
+    /// status is taken from the script's result if it is an integer from 0 to 255 or
+    /// `false`.
+    Run {
+        /// Source or bytecode file to execute
+        file: Option<String>,
+
+        /// Arguments passed to the script
+        #[clap(last = true)]
+        args: Vec<String>,
+    },
+
+    /// Parse and compile source files without executing them
+    Check {
+        /// Source files to check
+        #[clap(required = true)]
+        files: Vec<String>,
+
+        /// Output format of the report
+        #[clap(long, arg_enum, default_value = "human")]
+        format: OutputFormat,
+    },
+
+    /// Compile a source file to a bytecode file which `mag run` can execute directly
+    Build {
+        /// Source file to compile
+        file: String,
+
+        /// Path of the bytecode file, defaulting to the source path with a `.magb` extension
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+
+    /// Compile a source file and print a listing of its instructions
+    Disasm {
+        /// Source file to disassemble
+        file: String,
+    },
+
+    /// Inspect the configuration
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration, after applying environment variables and flags
+    Show,
 }
 
 fn main() {
     let args = Args::parse();
+    let (settings, path) = match load_config(&args) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{} {}", "error:".bright_red().bold(), e);
+            std::process::exit(1);
+        }
+    };
+    let config = settings.runtime_config();
+
+    match args.command {
+        Some(Command::Run {
+            file,
+            args: script_args,
+        }) => {
+            let result = match file {
+                Some(file) => commands::run::run_file(&file, &script_args, config),
+                None => commands::run::run_stdin(&script_args, config),
+            };
+
+            std::process::exit(commands::run::exit_code(&result));
+        }
+        Some(Command::Check { files, format }) => {
+            if !commands::check::check(&files, format, config) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Build { file, output }) => {
+            if !commands::build::build(&file, output.as_deref(), config) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Disasm { file }) => {
+            if !commands::disasm::disasm(&file, config) {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Config(ConfigCommand::Show)) => {
+            match path {
+                Some(path) => println!("# Loaded from '{}'", path.display()),
+                None => println!("# No configuration file found, using the defaults"),
+            }
 
-    // Check if we have a file argument
-    if let Some(file_path) = args.file {
-        run_file(&file_path, args.debug);
+            print!("{}", settings.to_toml());
+        }
+        None => launch(args.file, config, settings.repl),
+    }
+}
+
+/// Load the configuration file and apply the command line flags on top of it.
+fn load_config(args: &Args) -> Result<(Config, Option<PathBuf>), String> {
+    let (mut settings, path) = Config::load(args.config.as_deref()).map_err(|e| e.to_string())?;
+
+    if args.debug || args.no_debug {
+        settings.debug = args.debug;
+    }
+
+    if args.prelude || args.no_prelude {
+        settings.prelude = args.prelude;
+    }
+
+    if args.no_timeout {
+        settings.limits.timeout = None;
+    }
+
+    if let Some(timeout) = args.timeout {
+        config::timeout_duration(timeout).map_err(|reason| format!("`--timeout` {}", reason))?;
+        settings.limits.timeout = Some(timeout);
+    }
+
+    Ok((settings, path))
+}
+
+/// Run `mag` without a subcommand, executing a file or piped input if one is given
+/// and launching the REPL otherwise.
+fn launch(file: Option<String>, config: RuntimeConfig, settings: ReplConfig) {
+    if let Some(file_path) = file {
+        commands::run::run_file(&file_path, &[], config);
         return;
     }
 
     // Check if stdin has data (piped input)
     if !io::stdin().is_terminal() {
-        let mut source = String::new();
-        io::stdin()
-            .read_to_string(&mut source)
-            .expect("failed to read stdin");
-        run_source(source, args.debug);
+        commands::run::run_stdin(&[], config);
         return;
     }
 
     // Otherwise, launch the REPL
-    let mut repl = Repl::new(args.debug);
+    let mut repl = Repl::new(config, settings).unwrap_or_else(|e| {
+        eprint!("{}", e.to_diagnostic().render(commands::DIAGNOSTIC_CONTEXT));
+        std::process::exit(1);
+    });
     repl.launch().unwrap();
 }
 
-fn run_file(path: &str, debug: bool) {
-    match fs::read_to_string(path) {
-        Ok(source) => run_source(source, debug),
-        Err(e) => {
-            eprintln!(
-                "{} failed to read file '{}': {}",
-                "error:".bright_red().bold(),
-                path,
-                e
-            );
-            std::process::exit(1);
-        }
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
 
-fn run_source(source: String, debug: bool) {
-    let mut runtime = Runtime::new(RuntimeConfig { debug });
+    /// Load a configuration file with the given contents, with flags applied on top.
+    fn settings(name: &str, text: &str, flags: &[&str]) -> Result<Config, String> {
+        let path = env::temp_dir().join(format!("mag-flags-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, text).unwrap();
 
-    // Compile the entire source at once - parser now handles multi-line properly
-    let result = runtime.compiler.compile(source);
+        let path_arg = path.display().to_string();
+        let args = Args::parse_from(["mag", "--config", path_arg.as_str()].iter().chain(flags));
+        let result = load_config(&args);
+        fs::remove_file(&path).unwrap();
 
-    match result {
-        Ok(instructions) => {
-            if debug {
-                println!(
-                    "{}\n{:#?}",
-                    "instructions:".bright_blue().bold(),
-                    instructions
-                );
-            }
+        result.map(|(settings, _)| settings)
+    }
 
-            // Register all compiled methods in the VM's dispatch table
-            for reg in &runtime.compiler.method_registrations {
-                runtime.machine.register_method(
-                    reg.method_name.clone(),
-                    reg.pattern.clone(),
-                    reg.address,
-                );
-
-                if debug {
-                    println!(
-                        "Registered method {} with pattern {:?} at address {}",
-                        reg.method_name, reg.pattern, reg.address
-                    );
-                }
-            }
+    #[test]
+    fn flags_override_the_file_in_both_directions() {
+        let file = "debug = true\nprelude = false\n[limits]\ntimeout = 5";
 
-            for instruction in instructions.clone() {
-                runtime.machine.push_instruction(instruction);
-            }
+        let kept = settings("kept", file, &[]).unwrap();
+        assert!(kept.debug && !kept.prelude);
+        assert_eq!(kept.limits.timeout, Some(5.0));
 
-            if !instructions.is_empty() {
-                match runtime.machine.execute_until_eof() {
-                    Ok(_) => {
-                        // Print result from ret register if present
-                        if let Some(result) = runtime.machine.registers.get("ret") {
-                            if debug
-                                && !matches!(
-                                    result,
-                                    strontium::machine::register::RegisterValue::Empty
-                                )
-                            {
-                                println!("{}", result);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("{} {:?}", "error:".bright_red().bold(), e);
-                        match e {
-                            StrontiumError::BytecodeError(BytecodeError::UnexpectedEof(_)) => {
-                                eprintln!(
-                                    "{} {:?}",
-                                    "bytecode:".bright_blue().bold(),
-                                    runtime.machine.registers.get("bc").unwrap()
-                                );
-                            }
-                            _ => {}
-                        }
-                        std::process::exit(1);
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            eprintln!("{} {}", "error:".bright_red().bold(), e);
-            std::process::exit(1);
-        }
+        let overridden = settings(
+            "overridden",
+            file,
+            &["--no-debug", "--prelude", "--no-timeout"],
+        )
+        .unwrap();
+        assert!(!overridden.debug && overridden.prelude);
+        assert_eq!(overridden.limits.timeout, None);
+
+        let timeout = settings("timeout", file, &["--timeout", "0.5"]).unwrap();
+        assert_eq!(timeout.limits.timeout, Some(0.5));
+    }
+
+    #[test]
+    fn rejects_invalid_timeouts() {
+        assert!(settings("negative", "", &["--timeout=-1"]).is_err());
+        assert!(settings("zero", "", &["--timeout", "0"]).is_err());
+        assert_eq!(
+            settings("huge", "", &["--timeout", "1e20"])
+                .err()
+                .as_deref(),
+            Some("`--timeout` is too long")
+        );
     }
 }
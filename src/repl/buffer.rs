@@ -0,0 +1,569 @@
+use super::{is_identifier_continue, normalize_pasted_char};
+
+/// The most entries kept in the kill ring before the oldest ones are dropped.
+const KILL_RING_LIMIT: usize = 32;
+/// The most states kept for undo.
+const UNDO_LIMIT: usize = 200;
+
+/// The kind of the last change to the buffer, used to group consecutive changes of the
+/// same kind into a single undo step and consecutive kills into a single kill ring entry.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Insert,
+    Delete,
+    Kill,
+    Other,
+}
+
+/// The text being edited in the REPL together with the cursor, implementing the
+/// Emacs-style editing commands independently of the terminal.
+#[derive(Clone, Default)]
+pub(super) struct InputBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+    /// Killed text which can be yanked back, newest last.
+    kill_ring: Vec<String>,
+    /// The position and length of the last yank, and which kill ring entry it inserted,
+    /// so that Alt-Y can replace it with an older entry.
+    yanked: Option<(usize, usize, usize)>,
+    /// The text and cursor before each undoable change, newest last.
+    undo: Vec<(Vec<char>, usize)>,
+    last_edit: Option<Edit>,
+}
+
+impl InputBuffer {
+    /// Create an empty buffer which yanks from a kill ring filled while editing earlier
+    /// lines.
+    pub(super) fn with_kill_ring(kill_ring: Vec<String>) -> Self {
+        Self {
+            kill_ring,
+            ..Self::default()
+        }
+    }
+
+    pub(super) fn take_kill_ring(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.kill_ring)
+    }
+
+    pub(super) fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+    }
+
+    pub(super) fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    /// The identifier or qualified method name which ends at the cursor.
+    pub(super) fn word_before_cursor(&self) -> String {
+        let start = self.chars[..self.cursor]
+            .iter()
+            .rposition(|ch| !is_identifier_continue(*ch) && *ch != '.')
+            .map(|index| index + 1)
+            .unwrap_or(0);
+
+        self.chars[start..self.cursor].iter().collect()
+    }
+
+    /// Replace the whole text, like when stepping through the history. Since the new text
+    /// is unrelated to the old one, the undo history starts over.
+    pub(super) fn replace(&mut self, chars: Vec<char>) {
+        self.chars = chars;
+        self.cursor = self.chars.len();
+        self.undo.clear();
+        self.moved();
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.begin(Edit::Other);
+        self.chars.clear();
+        self.cursor = 0;
+    }
+
+    pub(super) fn insert_char(&mut self, ch: char) {
+        // Typing a word is undone as a whole, up to the whitespace before it.
+        if ch.is_whitespace() {
+            self.begin(Edit::Other);
+        } else {
+            self.begin(Edit::Insert);
+        }
+
+        self.insert(ch);
+    }
+
+    /// Insert text as a single undo step, like a paste or a completion.
+    pub(super) fn insert_text(&mut self, text: &str) {
+        self.begin(Edit::Other);
+
+        for ch in text.replace("\r\n", "\n").chars() {
+            self.insert(ch);
+        }
+    }
+
+    fn insert(&mut self, ch: char) {
+        if let Some(ch) = normalize_pasted_char(ch) {
+            self.chars.insert(self.cursor, ch);
+            self.cursor += 1;
+        }
+    }
+
+    pub(super) fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.begin(Edit::Delete);
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    pub(super) fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.begin(Edit::Delete);
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// The row and column of the cursor within the lines of the input.
+    pub(super) fn cursor_position(&self) -> (usize, usize) {
+        let row = self.chars[..self.cursor]
+            .iter()
+            .filter(|ch| **ch == '\n')
+            .count();
+
+        (row, self.cursor - self.line_start(self.cursor))
+    }
+
+    /// The index of the first character of the line containing the given index.
+    fn line_start(&self, index: usize) -> usize {
+        self.chars[..index]
+            .iter()
+            .rposition(|ch| *ch == '\n')
+            .map(|newline| newline + 1)
+            .unwrap_or(0)
+    }
+
+    /// The index of the newline ending the line containing the given index, or the end of
+    /// the input on the last line.
+    fn line_end(&self, index: usize) -> usize {
+        self.chars[index..]
+            .iter()
+            .position(|ch| *ch == '\n')
+            .map(|newline| index + newline)
+            .unwrap_or(self.chars.len())
+    }
+
+    pub(super) fn move_home(&mut self) {
+        self.moved();
+        self.cursor = self.line_start(self.cursor);
+    }
+
+    pub(super) fn move_end(&mut self) {
+        self.moved();
+        self.cursor = self.line_end(self.cursor);
+    }
+
+    pub(super) fn move_to_end(&mut self) {
+        self.moved();
+        self.cursor = self.chars.len();
+    }
+
+    /// Move the cursor to the same column of the previous line, returning false if it is
+    /// already on the first line.
+    pub(super) fn move_up(&mut self) -> bool {
+        let start = self.line_start(self.cursor);
+
+        if start == 0 {
+            return false;
+        }
+
+        let column = self.cursor - start;
+        let previous = self.line_start(start - 1);
+        self.moved();
+        self.cursor = (previous + column).min(start - 1);
+        true
+    }
+
+    /// Move the cursor to the same column of the next line, returning false if it is
+    /// already on the last line.
+    pub(super) fn move_down(&mut self) -> bool {
+        let end = self.line_end(self.cursor);
+
+        if end == self.chars.len() {
+            return false;
+        }
+
+        let column = self.cursor - self.line_start(self.cursor);
+        self.moved();
+        self.cursor = (end + 1 + column).min(self.line_end(end + 1));
+        true
+    }
+
+    pub(super) fn move_left(&mut self) {
+        self.moved();
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub(super) fn move_right(&mut self) {
+        self.moved();
+
+        if self.cursor < self.chars.len() {
+            self.cursor += 1;
+        }
+    }
+
+    /// Move the cursor to the start of the current or previous word.
+    pub(super) fn move_word_left(&mut self) {
+        self.moved();
+        self.cursor = self.word_start(self.cursor);
+    }
+
+    /// Move the cursor to the end of the current or next word.
+    pub(super) fn move_word_right(&mut self) {
+        self.moved();
+        self.cursor = self.word_end(self.cursor);
+    }
+
+    fn word_start(&self, mut index: usize) -> usize {
+        while index > 0 && !is_word_char(self.chars[index - 1]) {
+            index -= 1;
+        }
+
+        while index > 0 && is_word_char(self.chars[index - 1]) {
+            index -= 1;
+        }
+
+        index
+    }
+
+    fn word_end(&self, mut index: usize) -> usize {
+        while index < self.chars.len() && !is_word_char(self.chars[index]) {
+            index += 1;
+        }
+
+        while index < self.chars.len() && is_word_char(self.chars[index]) {
+            index += 1;
+        }
+
+        index
+    }
+
+    /// Kill from the cursor to the end of the line, or the line break itself if the
+    /// cursor is already at the end of a line.
+    pub(super) fn kill_to_end(&mut self) {
+        let end = match self.line_end(self.cursor) {
+            end if end == self.cursor && end < self.chars.len() => end + 1,
+            end => end,
+        };
+
+        self.kill(self.cursor, end);
+    }
+
+    /// Kill from the start of the line to the cursor.
+    pub(super) fn kill_to_start(&mut self) {
+        self.kill(self.line_start(self.cursor), self.cursor);
+    }
+
+    /// Kill from the cursor back to the previous whitespace, like Ctrl-W in shells.
+    pub(super) fn kill_word_before(&mut self) {
+        let mut start = self.cursor;
+
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+
+        self.kill(start, self.cursor);
+    }
+
+    /// Kill from the cursor back to the start of the current or previous word.
+    pub(super) fn kill_word_left(&mut self) {
+        self.kill(self.word_start(self.cursor), self.cursor);
+    }
+
+    /// Kill from the cursor to the end of the current or next word.
+    pub(super) fn kill_word_right(&mut self) {
+        self.kill(self.cursor, self.word_end(self.cursor));
+    }
+
+    /// Remove the text between two indices and put it into the kill ring. Consecutive
+    /// kills are collected into a single entry, so they can be yanked back at once.
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+
+        let appending = self.last_edit == Some(Edit::Kill);
+        self.begin(Edit::Kill);
+
+        let killed: String = self.chars.drain(start..end).collect();
+        let backwards = end == self.cursor;
+        self.cursor = start;
+
+        match self.kill_ring.last_mut() {
+            Some(entry) if appending && backwards => entry.insert_str(0, &killed),
+            Some(entry) if appending => entry.push_str(&killed),
+            _ => {
+                self.kill_ring.push(killed);
+
+                if self.kill_ring.len() > KILL_RING_LIMIT {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Insert the most recently killed text at the cursor.
+    pub(super) fn yank(&mut self) {
+        if let Some(index) = self.kill_ring.len().checked_sub(1) {
+            self.yank_entry(index);
+        }
+    }
+
+    /// Replace the text inserted by the last yank with the next older kill ring entry.
+    pub(super) fn yank_pop(&mut self) {
+        if let Some((start, length, index)) = self.yanked {
+            self.chars.drain(start..start + length);
+            self.cursor = start;
+
+            let previous = index.checked_sub(1).unwrap_or(self.kill_ring.len() - 1);
+            self.yank_entry(previous);
+        }
+    }
+
+    fn yank_entry(&mut self, index: usize) {
+        self.begin(Edit::Other);
+
+        let start = self.cursor;
+        let text = self.kill_ring[index].clone();
+
+        for ch in text.chars() {
+            self.insert(ch);
+        }
+
+        self.yanked = Some((start, self.cursor - start, index));
+    }
+
+    /// Swap the characters before and at the cursor and move past them. At the end of the
+    /// line, the two characters before the cursor are swapped instead.
+    pub(super) fn transpose(&mut self) {
+        let at_end = self.cursor == self.line_end(self.cursor);
+        let index = if at_end {
+            self.cursor.saturating_sub(1)
+        } else {
+            self.cursor
+        };
+
+        if index == 0 || index <= self.line_start(self.cursor) {
+            return;
+        }
+
+        self.begin(Edit::Other);
+        self.chars.swap(index - 1, index);
+        self.cursor = index + 1;
+    }
+
+    /// Restore the text and cursor from before the last change.
+    pub(super) fn undo(&mut self) {
+        if let Some((chars, cursor)) = self.undo.pop() {
+            self.chars = chars;
+            self.cursor = cursor;
+        }
+
+        self.moved();
+    }
+
+    /// Prepare for a change, saving the current state for undo unless the change
+    /// continues the previous one.
+    fn begin(&mut self, edit: Edit) {
+        let continues = self.last_edit == Some(edit) && matches!(edit, Edit::Insert | Edit::Delete);
+
+        if !continues {
+            self.undo.push((self.chars.clone(), self.cursor));
+
+            if self.undo.len() > UNDO_LIMIT {
+                self.undo.remove(0);
+            }
+        }
+
+        self.last_edit = Some(edit);
+        self.yanked = None;
+    }
+
+    /// End the current group of changes after the cursor was moved.
+    fn moved(&mut self) {
+        self.last_edit = None;
+        self.yanked = None;
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(text: &str) -> InputBuffer {
+        let mut buffer = InputBuffer::default();
+        buffer.insert_text(text);
+        buffer
+    }
+
+    #[test]
+    fn appends_consecutive_kills_forwards() {
+        let mut buffer = input("foo bar baz");
+        buffer.move_word_left();
+        buffer.move_word_left();
+        buffer.move_word_left();
+
+        buffer.kill_word_right();
+        buffer.kill_word_right();
+        assert_eq!(buffer.text(), " baz");
+        assert_eq!(buffer.take_kill_ring(), ["foo bar"]);
+    }
+
+    #[test]
+    fn prepends_consecutive_kills_backwards() {
+        let mut buffer = input("foo bar baz");
+
+        buffer.kill_word_left();
+        buffer.kill_word_left();
+        assert_eq!(buffer.text(), "foo ");
+
+        buffer.yank();
+        assert_eq!(buffer.text(), "foo bar baz");
+    }
+
+    #[test]
+    fn starts_a_new_kill_after_moving() {
+        let mut buffer = input("foo bar");
+
+        buffer.kill_word_left();
+        buffer.move_left();
+        buffer.kill_word_left();
+        assert_eq!(buffer.take_kill_ring(), ["bar", "foo"]);
+    }
+
+    #[test]
+    fn yank_pop_cycles_through_the_kill_ring() {
+        let mut buffer = InputBuffer::with_kill_ring(vec!["one".into(), "two".into()]);
+        buffer.insert_text("x ");
+
+        buffer.yank();
+        assert_eq!(buffer.text(), "x two");
+
+        buffer.yank_pop();
+        assert_eq!(buffer.text(), "x one");
+
+        buffer.yank_pop();
+        assert_eq!(buffer.text(), "x two");
+        assert_eq!(buffer.cursor_position(), (0, 5));
+    }
+
+    #[test]
+    fn yank_pop_needs_a_yank_first() {
+        let mut buffer = InputBuffer::with_kill_ring(vec!["one".into()]);
+        buffer.insert_text("x");
+
+        buffer.yank_pop();
+        assert_eq!(buffer.text(), "x");
+    }
+
+    #[test]
+    fn transposes_the_characters_before_the_end_of_a_line() {
+        let mut buffer = input("ab\ncd");
+        buffer.move_up();
+
+        buffer.transpose();
+        assert_eq!(buffer.text(), "ba\ncd");
+        assert_eq!(buffer.cursor_position(), (0, 2));
+    }
+
+    #[test]
+    fn transposes_around_the_cursor() {
+        let mut buffer = input("abc");
+        buffer.move_left();
+        buffer.move_left();
+
+        buffer.transpose();
+        assert_eq!(buffer.text(), "bac");
+        assert_eq!(buffer.cursor_position(), (0, 2));
+    }
+
+    #[test]
+    fn does_not_transpose_across_lines() {
+        let mut buffer = input("ab\ncd");
+        buffer.move_home();
+
+        buffer.transpose();
+        assert_eq!(buffer.text(), "ab\ncd");
+
+        let mut buffer = input("ab\nc");
+        buffer.transpose();
+        assert_eq!(buffer.text(), "ab\nc");
+    }
+
+    #[test]
+    fn undoes_typed_words_one_at_a_time() {
+        let mut buffer = InputBuffer::default();
+
+        for ch in "foo bar".chars() {
+            buffer.insert_char(ch);
+        }
+
+        buffer.undo();
+        assert_eq!(buffer.text(), "foo ");
+        buffer.undo();
+        assert_eq!(buffer.text(), "foo");
+        buffer.undo();
+        assert_eq!(buffer.text(), "");
+    }
+
+    #[test]
+    fn undoes_consecutive_deletions_together() {
+        let mut buffer = input("abc");
+
+        buffer.backspace();
+        buffer.backspace();
+        assert_eq!(buffer.text(), "a");
+
+        buffer.undo();
+        assert_eq!(buffer.text(), "abc");
+    }
+
+    #[test]
+    fn moves_up_and_down_keeping_the_column() {
+        let mut buffer = input("first\nsecond\nthird");
+        buffer.move_left();
+
+        assert!(buffer.move_up());
+        assert_eq!(buffer.cursor_position(), (1, 4));
+        assert!(buffer.move_up());
+        assert_eq!(buffer.cursor_position(), (0, 4));
+        assert!(!buffer.move_up());
+
+        assert!(buffer.move_down());
+        assert!(buffer.move_down());
+        assert_eq!(buffer.cursor_position(), (2, 4));
+        assert!(!buffer.move_down());
+    }
+
+    #[test]
+    fn clamps_the_column_to_shorter_lines() {
+        let mut buffer = input("ab\nlong line");
+        assert!(buffer.move_up());
+        assert_eq!(buffer.cursor_position(), (0, 2));
+
+        let mut buffer = input("long line\nab");
+        buffer.move_up();
+        buffer.move_end();
+        assert!(buffer.move_down());
+        assert_eq!(buffer.cursor_position(), (1, 2));
+    }
+}
@@ -0,0 +1,164 @@
+use magc::types::{Keyword, TokenKind};
+
+use mag_lang::runtime::Runtime;
+
+use super::{display_width, token_text, InputBuffer};
+
+/// The most candidates listed in the completion menu at once.
+const MENU_LIMIT: usize = 40;
+
+/// What pressing Tab did to the input.
+pub enum Completion {
+    /// Nothing matched the word before the cursor.
+    None,
+    /// The word was extended, either to the only match or to the prefix all matches share.
+    Completed,
+    /// The word is ambiguous, so the matching candidates should be shown.
+    Ambiguous(Vec<String>),
+}
+
+/// Complete the word before the cursor from a sorted list of candidates.
+pub fn complete(buffer: &mut InputBuffer, candidates: &[String]) -> Completion {
+    let prefix = buffer.word_before_cursor();
+    let matches: Vec<&String> = candidates
+        .iter()
+        .filter(|candidate| candidate.starts_with(&prefix))
+        .collect();
+
+    // A word which is complete already only needs a menu if longer candidates extend it.
+    if matches.iter().all(|candidate| **candidate == prefix) {
+        return Completion::None;
+    }
+
+    let common = match matches.as_slice() {
+        [] => return Completion::None,
+        [candidate] => candidate.as_str(),
+        [first, rest @ ..] => rest.iter().fold(first.as_str(), |common, candidate| {
+            common_prefix(common, candidate)
+        }),
+    };
+
+    if common.len() > prefix.len() {
+        buffer.insert_text(&common[prefix.len()..]);
+        Completion::Completed
+    } else {
+        Completion::Ambiguous(matches.into_iter().cloned().collect())
+    }
+}
+
+/// Lay out completion candidates in columns fitting the given terminal width.
+pub fn menu_rows(candidates: &[String], width: usize) -> Vec<String> {
+    let shown = &candidates[..candidates.len().min(MENU_LIMIT)];
+    let column_width = shown
+        .iter()
+        .map(|candidate| display_width(candidate))
+        .max()
+        .unwrap_or(0)
+        + 2;
+    let columns = (width / column_width).max(1);
+
+    let mut rows: Vec<String> = shown
+        .chunks(columns)
+        .map(|row| {
+            row.iter()
+                .map(|candidate| {
+                    let padding = column_width - display_width(candidate);
+                    format!("{}{}", candidate, " ".repeat(padding))
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+
+    if candidates.len() > shown.len() {
+        rows.push(format!("... and {} more", candidates.len() - shown.len()));
+    }
+
+    rows
+}
+
+/// Find the names of the variables and constants declared by a piece of source code.
+pub fn declared_globals(source: &str) -> Vec<String> {
+    let chars: Vec<char> = source.chars().collect();
+    let tokens = Runtime::tokenize(source);
+
+    tokens
+        .windows(2)
+        .filter(|pair| {
+            matches!(
+                pair[0].kind,
+                TokenKind::Keyword(Keyword::Var | Keyword::Const)
+            ) && pair[1].kind == TokenKind::Identifier
+        })
+        .map(|pair| token_text(&chars, &pair[1]))
+        .collect()
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let length = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((index, _), _)| index)
+        .unwrap_or_else(|| a.len().min(b.len()));
+
+    &a[..length]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn offers_exact_matches_alongside_longer_ones() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert_text("fib");
+
+        match complete(&mut buffer, &candidates(&["fib", "fibonacci"])) {
+            Completion::Ambiguous(matches) => assert_eq!(matches, ["fib", "fibonacci"]),
+            _ => panic!("expected both candidates"),
+        }
+        assert_eq!(buffer.text(), "fib");
+        assert!(matches!(
+            complete(&mut buffer, &candidates(&["fib"])),
+            Completion::None
+        ));
+    }
+
+    #[test]
+    fn completes_to_the_common_prefix() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert_text("fi");
+
+        assert!(matches!(
+            complete(&mut buffer, &candidates(&["fib", "fibonacci", "map"])),
+            Completion::Completed
+        ));
+        assert_eq!(buffer.text(), "fib");
+    }
+
+    #[test]
+    fn aligns_menu_columns_by_display_width() {
+        let rows = menu_rows(&candidates(&["日本", "ab", "cd"]), 18);
+
+        assert_eq!(rows, ["日本  ab    cd"]);
+    }
+
+    #[test]
+    fn finds_declared_globals() {
+        assert_eq!(
+            declared_globals("var count = 1\nconst limit = 10\ncount + limit"),
+            ["count", "limit"]
+        );
+    }
+
+    #[test]
+    fn skips_declarations_in_strings_and_comments() {
+        assert!(declared_globals("\"var x = 1\" // const y = 2").is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use magc::types::{Literal, TokenKind};
+
+use mag_lang::runtime::{Runtime, Span};
+
+use super::ReplTheme;
+
+/// The role of a character in the input, which decides its color.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Plain,
+    Keyword,
+    TypeName,
+    String,
+    Number,
+    Punctuation,
+    Operator,
+    Comment,
+    Error,
+}
+
+/// Highlight the input using the tokens produced by the real lexer, returning one
+/// highlighted string for each line.
+///
+/// The lexer accepts partial input, so an unterminated string at the end is highlighted
+/// as a string up to the end of the input. The span of a syntax error is marked on top
+/// of that.
+pub(super) fn highlight_lines(input: &str, theme: &ReplTheme, error: Option<Span>) -> Vec<String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut styles = styles(&chars);
+
+    if let Some(span) = error {
+        // Mark at least one character, so errors at a single position stay visible.
+        let start = span.start.min(chars.len());
+        let end = span.end.max(start + 1).min(chars.len());
+        styles[start..end].fill(Style::Error);
+    }
+
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut run = String::new();
+    let mut run_style = Style::Plain;
+
+    for (ch, style) in chars.iter().zip(styles) {
+        if *ch == '\n' || style != run_style {
+            line.push_str(&paint(theme, run_style, &run));
+            run.clear();
+            run_style = style;
+        }
+
+        if *ch == '\n' {
+            lines.push(std::mem::take(&mut line));
+        } else {
+            run.push(*ch);
+        }
+    }
+
+    line.push_str(&paint(theme, run_style, &run));
+    lines.push(line);
+    lines
+}
+
+fn styles(chars: &[char]) -> Vec<Style> {
+    let mut styles = vec![Style::Plain; chars.len()];
+
+    for token in Runtime::tokenize(&chars.iter().collect::<String>()) {
+        let start = token.start_pos.min(chars.len());
+        let stop = token.end_pos.min(chars.len());
+        styles[start..stop].fill(classify(&token.kind));
+    }
+
+    styles
+}
+
+fn classify(kind: &TokenKind) -> Style {
+    match kind {
+        TokenKind::Identifier => Style::Plain,
+        // Every kind of keyword is part of the language, so new keywords are
+        // highlighted without changes here.
+        TokenKind::Keyword(_) | TokenKind::Literal(Literal::Boolean) => Style::Keyword,
+        TokenKind::Type => Style::TypeName,
+        TokenKind::Literal(Literal::String) => Style::String,
+        TokenKind::Literal(Literal::Int | Literal::Float) => Style::Number,
+        TokenKind::Comment => Style::Comment,
+        TokenKind::LeftParen
+        | TokenKind::RightParen
+        | TokenKind::LeftBracket
+        | TokenKind::RightBracket
+        | TokenKind::Dot
+        | TokenKind::Comma
+        | TokenKind::Colon => Style::Punctuation,
+        _ => Style::Operator,
+    }
+}
+
+fn paint(theme: &ReplTheme, style: Style, text: &str) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+
+    match style {
+        Style::Plain => text.to_string(),
+        Style::Keyword => theme.keyword(text),
+        Style::TypeName => theme.type_name(text),
+        Style::String => theme.string(text),
+        Style::Number => theme.number(text),
+        Style::Punctuation => theme.punctuation(text),
+        Style::Operator => theme.operator(text),
+        Style::Comment => theme.comment(text),
+        Style::Error => theme.error(text),
+    }
+}
@@ -0,0 +1,105 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// A position on the terminal, counted in rows from the first row of the input and in
+/// columns from the left edge.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct Position {
+    pub(super) row: usize,
+    pub(super) column: usize,
+}
+
+impl Position {
+    /// The start of the row below this position.
+    pub(super) fn next_row(self) -> Self {
+        Self {
+            row: self.row + 1,
+            column: 0,
+        }
+    }
+
+    /// Find where the terminal leaves the cursor after printing text from this position,
+    /// given its width in columns.
+    ///
+    /// Text wraps onto the next row after the last column, and a wide character which
+    /// doesn't fit onto a row anymore is moved to the next one as a whole, like terminals
+    /// do. The text must not contain line breaks or escape sequences.
+    pub(super) fn advance(mut self, text: &str, columns: usize) -> Self {
+        for ch in text.chars() {
+            self = self.wrap_before(ch, columns);
+            self.column += ch.width().unwrap_or(0);
+        }
+
+        self
+    }
+
+    /// The position a character printed from this position starts at, which is the
+    /// start of the next row if it doesn't fit onto this one anymore.
+    pub(super) fn wrap_before(self, ch: char, columns: usize) -> Self {
+        let width = ch.width().unwrap_or(0);
+
+        if self.column > 0 && self.column + width > columns.max(1) {
+            self.next_row()
+        } else {
+            self
+        }
+    }
+
+    /// The column the cursor is shown in. After printing up to the right edge, terminals
+    /// keep the cursor in the last column until the next character wraps.
+    pub(super) fn display_column(self, columns: usize) -> u16 {
+        self.column
+            .min(columns.saturating_sub(1))
+            .min(u16::MAX as usize) as u16
+    }
+}
+
+/// The number of columns text takes up on the terminal, counting wide characters like
+/// CJK ideographs and most emoji as two columns and combining marks as none.
+pub(super) fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(row: usize, column: usize) -> Position {
+        Position { row, column }
+    }
+
+    #[test]
+    fn advances_within_a_row() {
+        assert_eq!(Position::default().advance("hello", 80), at(0, 5));
+    }
+
+    #[test]
+    fn stays_in_the_last_column_until_the_next_character() {
+        assert_eq!(Position::default().advance("abcd", 4), at(0, 4));
+        assert_eq!(Position::default().advance("abcde", 4), at(1, 1));
+    }
+
+    #[test]
+    fn counts_wide_characters_as_two_columns() {
+        assert_eq!(Position::default().advance("日本", 80), at(0, 4));
+        assert_eq!(Position::default().advance("e\u{301}", 80), at(0, 1));
+    }
+
+    #[test]
+    fn moves_wide_characters_that_dont_fit_to_the_next_row() {
+        assert_eq!(Position::default().advance("abc日", 4), at(1, 2));
+        assert_eq!(at(0, 3).wrap_before('日', 4), at(1, 0));
+        assert_eq!(at(0, 2).wrap_before('日', 4), at(0, 2));
+    }
+
+    #[test]
+    fn never_wraps_at_the_start_of_a_row() {
+        assert_eq!(at(2, 0).wrap_before('日', 1), at(2, 0));
+        assert_eq!(at(2, 0).wrap_before('a', 0), at(2, 0));
+    }
+
+    #[test]
+    fn keeps_the_cursor_inside_the_terminal() {
+        assert_eq!(at(0, 4).display_column(4), 3);
+        assert_eq!(at(0, 2).display_column(4), 2);
+    }
+}
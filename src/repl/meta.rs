@@ -0,0 +1,265 @@
+use std::time::{Duration, Instant};
+
+use colored::*;
+use mag_lang::runtime::{format_pattern, Source};
+
+use super::{completion, Repl};
+use crate::commands;
+
+/// The meta-commands understood by the REPL, with their arguments and a description.
+const HELP: &[(&str, &str)] = &[
+    (":help", "Show this list of commands"),
+    (
+        ":load <file>",
+        "Run a file, keeping its definitions in the session",
+    ),
+    (
+        ":reset",
+        "Discard all definitions and start a fresh runtime",
+    ),
+    (
+        ":methods",
+        "List the registered multimethods and their patterns",
+    ),
+    (
+        ":type <expr>",
+        "Run an expression in the session and show the type of its value",
+    ),
+    (
+        ":time <expr>",
+        "Run an expression in the session and show how long it took",
+    ),
+    (
+        ":debug on|off",
+        "Toggle the instruction listing, method registration and execution trace",
+    ),
+    (":quit, :exit", "Leave the REPL"),
+];
+
+/// A command entered in the REPL which is handled by the REPL itself, rather than being
+/// executed as Mag code.
+#[derive(Debug, PartialEq)]
+pub enum MetaCommand {
+    Help,
+    Load(String),
+    Reset,
+    Methods,
+    Type(String),
+    Time(String),
+    Debug(bool),
+    Quit,
+}
+
+impl MetaCommand {
+    /// Returns true if the input should be parsed as a meta-command.
+    pub fn is_command(input: &str) -> bool {
+        input.trim_start().starts_with(':')
+    }
+
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let (name, argument) = match input.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (input, ""),
+        };
+
+        let command = match (name, argument) {
+            (":help" | ":h" | ":?", "") => Self::Help,
+            (":load" | ":l", "") => return Err("usage: :load <file>".to_string()),
+            (":load" | ":l", file) => Self::Load(file.to_string()),
+            (":reset", "") => Self::Reset,
+            (":methods", "") => Self::Methods,
+            (":type" | ":t", "") => return Err("usage: :type <expr>".to_string()),
+            (":type" | ":t", expression) => Self::Type(expression.to_string()),
+            (":time", "") => return Err("usage: :time <expr>".to_string()),
+            (":time", expression) => Self::Time(expression.to_string()),
+            (":debug", "on") => Self::Debug(true),
+            (":debug", "off") => Self::Debug(false),
+            (":debug", _) => return Err("usage: :debug on|off".to_string()),
+            (":quit" | ":exit" | ":q", "") => Self::Quit,
+            (":help" | ":h" | ":?" | ":reset" | ":methods" | ":quit" | ":exit" | ":q", _) => {
+                return Err(format!("{} doesn't take any arguments", name))
+            }
+            _ => {
+                return Err(format!(
+                    "unknown command `{}`, type :help for a list of commands",
+                    name
+                ))
+            }
+        };
+
+        Ok(command)
+    }
+}
+
+impl Repl {
+    /// Run a meta-command, returning false if the REPL should exit.
+    pub(super) fn run_command(&mut self, command: MetaCommand) -> bool {
+        match command {
+            MetaCommand::Help => print_help(),
+            MetaCommand::Load(path) => self.load(&path),
+            MetaCommand::Reset => match commands::new_runtime(self.runtime.config.clone()) {
+                Ok(runtime) => {
+                    self.runtime = runtime;
+                    self.globals.clear();
+                    println!("{}", "runtime reset".dimmed());
+                }
+                Err(e) => print!("{}", e.to_diagnostic().render(0)),
+            },
+            MetaCommand::Methods => self.print_methods(),
+            MetaCommand::Type(expression) => {
+                if let Some(value) = self.execute_line(expression) {
+                    println!("{}", value.type_name());
+                }
+            }
+            MetaCommand::Time(expression) => {
+                let start = Instant::now();
+                let value = self.execute_line(expression);
+                let elapsed = start.elapsed();
+
+                if let Some(value) = value {
+                    self.print_value(&value);
+                    println!("{}", format!("took {}", format_duration(elapsed)).dimmed());
+                }
+            }
+            MetaCommand::Debug(debug) => {
+                self.runtime.set_debug(debug);
+                println!(
+                    "{}",
+                    format!("debug output {}", if debug { "on" } else { "off" }).dimmed()
+                );
+            }
+            MetaCommand::Quit => return false,
+        }
+
+        true
+    }
+
+    fn load(&mut self, path: &str) {
+        let text = match commands::read_source(path) {
+            Some(text) => text,
+            None => return,
+        };
+        let globals = completion::declared_globals(&text);
+
+        if self.execute(Source::new(path, text)).is_some() {
+            self.globals.extend(globals);
+            println!("{}", format!("loaded {}", path).dimmed());
+        }
+    }
+
+    fn print_methods(&self) {
+        let mut methods: Vec<(String, String, String)> = self
+            .runtime
+            .methods()
+            .map(|method| {
+                (
+                    method.name,
+                    format_pattern(&method.pattern),
+                    format!("at {}", method.address),
+                )
+            })
+            .chain(self.runtime.native_methods().map(|method| {
+                (
+                    method.name.clone(),
+                    format_pattern(&method.pattern),
+                    "native".to_string(),
+                )
+            }))
+            .collect();
+        methods.sort();
+
+        if methods.is_empty() {
+            println!("{}", "no methods defined".dimmed());
+        }
+
+        for (name, pattern, origin) in methods {
+            println!("{}({}) {}", name.bold(), pattern, origin.dimmed());
+        }
+    }
+}
+
+fn print_help() {
+    let width = HELP.iter().map(|(usage, _)| usage.len()).max().unwrap_or(0);
+
+    for (usage, description) in HELP {
+        let usage = format!("{:<width$}", usage, width = width);
+        println!("  {}  {}", usage.bold(), description);
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration.as_secs() > 0 {
+        format!("{:.3}s", duration.as_secs_f64())
+    } else if duration.as_millis() > 0 {
+        format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{}µs", duration.as_micros())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands_and_aliases() {
+        for (input, command) in [
+            (":help", MetaCommand::Help),
+            (":?", MetaCommand::Help),
+            (
+                ":l  script.mag ",
+                MetaCommand::Load("script.mag".to_string()),
+            ),
+            (":reset", MetaCommand::Reset),
+            (":methods", MetaCommand::Methods),
+            (":t 1 + 2", MetaCommand::Type("1 + 2".to_string())),
+            (":time fib(20)", MetaCommand::Time("fib(20)".to_string())),
+            (":exit", MetaCommand::Quit),
+            ("  :q", MetaCommand::Quit),
+        ] {
+            assert_eq!(MetaCommand::parse(input), Ok(command), "{}", input);
+        }
+    }
+
+    #[test]
+    fn parses_debug_switches() {
+        assert_eq!(
+            MetaCommand::parse(":debug on"),
+            Ok(MetaCommand::Debug(true))
+        );
+        assert_eq!(
+            MetaCommand::parse(":debug off"),
+            Ok(MetaCommand::Debug(false))
+        );
+        assert!(MetaCommand::parse(":debug").is_err());
+        assert!(MetaCommand::parse(":debug yes").is_err());
+    }
+
+    #[test]
+    fn rejects_missing_and_extra_arguments() {
+        assert_eq!(
+            MetaCommand::parse(":load"),
+            Err("usage: :load <file>".to_string())
+        );
+        assert_eq!(
+            MetaCommand::parse(":type  "),
+            Err("usage: :type <expr>".to_string())
+        );
+        for name in [":reset", ":help", ":h", ":?", ":quit", ":exit", ":q"] {
+            assert_eq!(
+                MetaCommand::parse(&format!("{} now", name)),
+                Err(format!("{} doesn't take any arguments", name))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let error = MetaCommand::parse(":frobnicate").unwrap_err();
+
+        assert!(error.starts_with("unknown command `:frobnicate`"));
+        assert!(MetaCommand::is_command("  :frobnicate"));
+        assert!(!MetaCommand::is_command("1 + 2"));
+    }
+}
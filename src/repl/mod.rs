@@ -1,4 +1,11 @@
-use std::collections::VecDeque;
+mod buffer;
+mod completion;
+mod highlight;
+mod layout;
+mod meta;
+mod theme;
+
+use std::collections::{BTreeSet, VecDeque};
 use std::fs;
 use std::io::{self, Write};
 use std::path::PathBuf;
@@ -9,57 +16,119 @@ use crossterm::{
         self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEvent, KeyModifiers,
     },
     style::Print,
-    terminal, ExecutableCommand, QueueableCommand, Result,
+    terminal::{self, ClearType},
+    ExecutableCommand, QueueableCommand, Result,
 };
 use std::time::Duration;
 
-use crate::runtime::{Runtime, RuntimeConfig};
+use self::buffer::InputBuffer;
+use self::completion::Completion;
+use self::layout::{display_width, Position};
+use self::meta::MetaCommand;
+use self::theme::ReplTheme;
+use crate::commands;
+use crate::config::ReplConfig;
 use colored::*;
+use mag_lang::runtime::{
+    quote_string, CancellationToken, RunOutcome, Runtime, RuntimeConfig, RuntimeError, Source,
+    Span, Value,
+};
+use magc::types::{Keyword, Literal, Token, TokenKind};
 use signal_hook::{consts::SIGINT, flag};
 
-use strontium::machine::bytecode::BytecodeError;
-use strontium::machine::CancellationToken;
-use strontium::types::StrontiumError;
-
-const PROMPT: &str = ">>> ";
-const PROMPT_WIDTH: usize = 4;
-const HISTORY_LIMIT: usize = 1000;
 const PASTE_DEBOUNCE: Duration = Duration::from_millis(8);
 
+/// Words which may be keywords of the language. Only those the lexer actually reads as
+/// keywords or booleans are offered as completions, see [`keywords`].
+const KEYWORDS: &[&str] = &[
+    "and",
+    "as",
+    "catch",
+    "case",
+    "const",
+    "def",
+    "do",
+    "else",
+    "end",
+    "enum",
+    "false",
+    "for",
+    "if",
+    "import",
+    "interface",
+    "it",
+    "match",
+    "or",
+    "return",
+    "then",
+    "this",
+    "true",
+    "var",
+    "while",
+    "with",
+];
+
 pub struct Repl {
     runtime: Runtime,
     cancellation: CancellationToken,
+    /// The number of lines executed so far, used to name each line's source.
+    line_count: usize,
+    /// The variables and constants declared in the session, offered as completions.
+    globals: BTreeSet<String>,
+    theme: ReplTheme,
+    settings: ReplConfig,
 }
 
 impl Repl {
-    pub fn new(debug: bool) -> Self {
-        Self {
-            runtime: Runtime::new(RuntimeConfig { debug }),
+    pub fn new(
+        config: RuntimeConfig,
+        settings: ReplConfig,
+    ) -> std::result::Result<Self, RuntimeError> {
+        Ok(Self {
+            runtime: commands::new_runtime(config)?,
             cancellation: CancellationToken::new(),
-        }
+            line_count: 0,
+            globals: BTreeSet::new(),
+            theme: ReplTheme::named(&settings.theme),
+            settings,
+        })
     }
 
     pub fn launch(&mut self) -> Result<()> {
-        println!("");
+        println!();
         println!("{}", std::fs::read_to_string("./logo.txt")?);
         flag::register(SIGINT, self.cancellation.flag())?;
 
-        let mut editor = LineEditor::new(Self::history_path(), ReplTheme::from_env());
+        let mut editor = LineEditor::new(&self.settings, self.theme.clone());
         editor.load_history();
 
         loop {
-            match editor.read_line()? {
-                ReadLine::Input(input) => {
-                    let trimmed = input.trim();
+            editor.set_candidates(self.completion_candidates());
+            let runtime = &mut self.runtime;
 
-                    match trimmed {
-                        "" => continue,
-                        ":quit" | ":exit" => break,
-                        _ => {}
+            match editor.read_line(|input| validate(runtime, input))? {
+                ReadLine::Input(input) => {
+                    if input.trim().is_empty() {
+                        continue;
                     }
 
                     editor.add_history(input.clone());
-                    self.execute_line(format!("{}\n", input));
+
+                    if !MetaCommand::is_command(&input) {
+                        if let Some(value) = self.execute_line(input) {
+                            self.print_value(&value);
+                        }
+                        continue;
+                    }
+
+                    match MetaCommand::parse(&input) {
+                        Ok(command) => {
+                            if !self.run_command(command) {
+                                break;
+                            }
+                        }
+                        Err(e) => println!("{} {}", "error:".bright_red().bold(), e),
+                    }
                 }
                 ReadLine::Interrupted => continue,
                 ReadLine::Eof => break,
@@ -71,82 +140,135 @@ impl Repl {
         Ok(())
     }
 
-    fn execute_line(&mut self, line: String) {
-        let result = self.runtime.compiler.compile(line.clone());
+    /// Execute a line of input, returning its value if it ran successfully.
+    fn execute_line(&mut self, line: String) -> Option<Value> {
+        self.line_count += 1;
+        let globals = completion::declared_globals(&line);
+        let source = Source::new(format!("<repl:{}>", self.line_count), format!("{}\n", line));
 
-        match result {
-            Ok(instructions) => {
-                if self.runtime.config.debug {
-                    println!(
-                        "{}\n{:#?}",
-                        "instructions:".bright_blue().bold(),
-                        instructions
-                    );
-                }
-
-                self.runtime.machine.reset();
-                self.runtime.machine.multimethod_table.clear();
-                for reg in &self.runtime.compiler.method_registrations {
-                    self.runtime.machine.register_method(
-                        reg.method_name.clone(),
-                        reg.pattern.clone(),
-                        reg.address,
-                    );
-                }
+        let value = self.execute(source)?;
+        self.globals.extend(globals);
+        Some(value)
+    }
 
-                for instruction in instructions.clone() {
-                    self.runtime.machine.push_instruction(instruction);
-                }
+    /// Print the value of an expression, unless it didn't produce one.
+    fn print_value(&self, value: &Value) {
+        if !value.is_empty() {
+            println!("{}", format_value(value, &self.theme));
+        }
+    }
 
-                if instructions.len() > 0 {
-                    self.cancellation.reset();
-                    match self
-                        .runtime
-                        .machine
-                        .execute_until_eof_cancellable(&self.cancellation)
-                    {
-                        Ok(_) => {}
-                        Err(StrontiumError::Interrupted) => {
-                            println!("\n{}", "interrupted".bright_yellow().bold());
-                            self.cancellation.reset();
-                        }
-                        Err(e) => {
-                            println!(
-                                "{} {}",
-                                "error:".bright_red().bold(),
-                                format!("{:?}", e).bold()
-                            );
-                            match e {
-                                StrontiumError::BytecodeError(BytecodeError::UnexpectedEof(_)) => {
-                                    println!(
-                                        "{} {:?}",
-                                        "bytecode:".bright_blue().bold(),
-                                        self.runtime.machine.registers.get("bc").unwrap()
-                                    );
-                                }
-
-                                _ => {}
-                            }
-                        }
-                    }
-                }
+    /// Run source code in the session, reporting any error.
+    fn execute(&mut self, source: Source) -> Option<Value> {
+        match self.runtime.run(source, &self.cancellation) {
+            RunOutcome::Value(value) => Some(value),
+            RunOutcome::Interrupted => {
+                println!("\n{}", "interrupted".bright_yellow().bold());
+                None
             }
-            Err(e) => {
-                println!(
-                    "{} {}",
-                    "error:".bright_red().bold(),
-                    format!("{}", e).bold()
-                );
-                println!("{}", "  |".blue().bold());
-                println!("{}    {}", "1 |".blue().bold(), line);
-                println!("{}", "  |".blue().bold());
+            RunOutcome::Error(e) => {
+                print!("{}", e.to_diagnostic().render(0));
+                None
+            }
+        }
+    }
+
+    /// Every word Tab can complete to: keywords, multimethod names and session globals.
+    fn completion_candidates(&self) -> Vec<String> {
+        let mut candidates: BTreeSet<String> = keywords().collect();
+
+        candidates.extend(self.runtime.methods().map(|method| method.name));
+        candidates.extend(
+            self.runtime
+                .native_methods()
+                .map(|method| method.name.clone()),
+        );
+        candidates.extend(self.globals.iter().cloned());
+
+        candidates.into_iter().collect()
+    }
+}
+
+/// The words of [`KEYWORDS`] which the lexer reads as a keyword or a boolean.
+fn keywords() -> impl Iterator<Item = String> {
+    KEYWORDS
+        .iter()
+        .filter(|word| {
+            matches!(
+                Runtime::tokenize(word).as_slice(),
+                [token] if matches!(
+                    token.kind,
+                    TokenKind::Keyword(_) | TokenKind::Literal(Literal::Boolean)
+                )
+            )
+        })
+        .map(|word| word.to_string())
+}
+
+/// Whether the input in the editor could be executed as it is.
+enum Validation {
+    Complete,
+    /// The input ends in the middle of an expression, like inside an open bracket or
+    /// `do` block, so Enter continues on a new line.
+    Incomplete,
+    /// No amount of further input could make the input valid.
+    Invalid {
+        span: Span,
+        message: String,
+    },
+}
+
+fn validate(runtime: &mut Runtime, input: &str) -> Validation {
+    if MetaCommand::is_command(input) {
+        return Validation::Complete;
+    }
+
+    if has_open_blocks(input) {
+        return Validation::Incomplete;
+    }
+
+    match runtime.check_syntax(Source::new("<repl>", input)) {
+        Ok(()) => Validation::Complete,
+        Err(e) if e.is_incomplete_input() => Validation::Incomplete,
+        Err(e) => {
+            let diagnostic = e.to_diagnostic();
+
+            match diagnostic.labels.first() {
+                Some(label) => Validation::Invalid {
+                    span: label.location.span,
+                    message: diagnostic.message,
+                },
+                None => Validation::Complete,
             }
         }
     }
+}
 
-    fn history_path() -> Option<PathBuf> {
-        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mag_history"))
+/// Returns true if the input contains brackets or `do`/`match` blocks which are opened
+/// but not closed yet. Extra closing brackets count as complete, so that the parser can
+/// report them.
+fn has_open_blocks(input: &str) -> bool {
+    let mut brackets = 0;
+    let mut blocks = 0;
+
+    for token in Runtime::tokenize(input) {
+        match token.kind {
+            TokenKind::LeftParen | TokenKind::LeftBracket => brackets += 1,
+            TokenKind::RightParen | TokenKind::RightBracket => brackets -= 1,
+            TokenKind::Keyword(Keyword::Do | Keyword::Match) => blocks += 1,
+            TokenKind::Keyword(Keyword::End) => blocks -= 1,
+            _ => {}
+        }
     }
+
+    brackets > 0 || blocks > 0
+}
+
+/// The text of a token in the input it was lexed from.
+fn token_text(chars: &[char], token: &Token) -> String {
+    chars[token.start_pos.min(chars.len())..token.end_pos.min(chars.len())]
+        .iter()
+        .collect()
 }
 
 enum ReadLine {
@@ -155,83 +277,243 @@ enum ReadLine {
     Eof,
 }
 
+/// Where the input is drawn on the terminal, relative to its first row.
+struct InputLayout {
+    /// The position after the last character of each line.
+    line_ends: Vec<Position>,
+    cursor: Position,
+}
+
+impl InputLayout {
+    /// The position after the last character of the input.
+    fn end(&self) -> Position {
+        self.line_ends.last().copied().unwrap_or_default()
+    }
+}
+
 struct LineEditor {
     history: Vec<String>,
     history_path: Option<PathBuf>,
+    history_limit: usize,
+    /// The prompts of the first and the following lines, padded to the same width.
+    prompt: String,
+    continuation_prompt: String,
     theme: ReplTheme,
-    rendered_width: usize,
+    /// The width of the terminal, which long lines wrap at.
+    columns: usize,
+    /// The number of rows drawn by the last render, and the row the cursor was left on.
+    rendered_rows: usize,
+    cursor_row: usize,
     pending_events: VecDeque<Event>,
     ignore_next_submit: bool,
+    /// The words Tab completes to, sorted alphabetically.
+    candidates: Vec<String>,
+    /// The rows of the completion menu shown below the input, if any.
+    menu: Vec<String>,
+    /// Killed text which can be yanked back, shared between all lines.
+    kill_ring: Vec<String>,
+    /// The syntax error in the current input and its message, shown while typing.
+    error: Option<(Span, String)>,
 }
 
 impl LineEditor {
-    fn new(history_path: Option<PathBuf>, theme: ReplTheme) -> Self {
+    fn new(settings: &ReplConfig, theme: ReplTheme) -> Self {
+        let width =
+            display_width(&settings.prompt).max(display_width(&settings.continuation_prompt));
+        let pad = |prompt: &str| format!("{}{}", prompt, " ".repeat(width - display_width(prompt)));
+
         Self {
             history: vec![],
-            history_path,
+            history_path: settings.history_path.clone(),
+            history_limit: settings.history_size,
+            prompt: pad(&settings.prompt),
+            continuation_prompt: pad(&settings.continuation_prompt),
             theme,
-            rendered_width: 0,
+            columns: 80,
+            rendered_rows: 1,
+            cursor_row: 0,
             pending_events: VecDeque::new(),
             ignore_next_submit: false,
+            candidates: vec![],
+            menu: vec![],
+            kill_ring: vec![],
+            error: None,
         }
     }
 
-    fn read_line(&mut self) -> Result<ReadLine> {
+    fn set_candidates(&mut self, candidates: Vec<String>) {
+        self.candidates = candidates;
+    }
+
+    /// Read a line of input, which may span several rows if `validate` reports that
+    /// Enter was pressed in the middle of an expression. Input which is definitely invalid
+    /// is underlined while typing.
+    fn read_line(&mut self, validate: impl FnMut(&str) -> Validation) -> Result<ReadLine> {
         let _raw = RawMode::enable()?;
+        let mut buffer = InputBuffer::with_kill_ring(std::mem::take(&mut self.kill_ring));
+        let result = self.edit(&mut buffer, validate);
+
+        self.kill_ring = buffer.take_kill_ring();
+        result
+    }
+
+    fn edit(
+        &mut self,
+        buffer: &mut InputBuffer,
+        mut validate: impl FnMut(&str) -> Validation,
+    ) -> Result<ReadLine> {
         let mut stdout = io::stdout();
-        let mut buffer = InputBuffer::new();
         let mut history_pos: Option<usize> = None;
         let mut draft: Vec<char> = vec![];
 
-        self.render(&mut stdout, &buffer)?;
+        self.columns = terminal::size()?.0 as usize;
+        self.rendered_rows = 1;
+        self.cursor_row = 0;
+        self.error = None;
+        self.render(&mut stdout, buffer)?;
 
         loop {
-            match self.read_event()? {
+            let event = self.read_event()?;
+
+            // The completion menu only stays visible until the next key press.
+            if !matches!(
+                event,
+                Event::Key(KeyEvent {
+                    code: KeyCode::Tab,
+                    ..
+                })
+            ) {
+                self.menu.clear();
+            }
+
+            match event {
                 Event::Paste(pasted) => {
                     buffer.insert_text(&pasted);
                     history_pos = None;
                 }
+                Event::Resize(columns, _) => {
+                    // The terminal rewraps the rows which were already drawn, so the
+                    // cursor is now where the input is laid out at the new width.
+                    self.columns = columns as usize;
+                    self.cursor_row = self.layout(buffer).cursor.row;
+                }
                 Event::Key(KeyEvent {
                     code, modifiers, ..
                 }) => match (code, modifiers) {
+                    (KeyCode::Enter, KeyModifiers::ALT) => {
+                        buffer.insert_char('\n');
+                        history_pos = None;
+                    }
                     (KeyCode::Enter, _) => {
                         if self.ignore_next_submit {
                             self.ignore_next_submit = false;
                             continue;
                         }
-                        stdout.queue(Print("\r\n"))?;
-                        stdout.flush()?;
-                        return Ok(ReadLine::Input(buffer.to_string()));
+
+                        // An empty last line submits incomplete input anyway, so its
+                        // error can be reported instead of asking for more lines.
+                        let input = buffer.text();
+                        let forced = input.rsplit('\n').next().unwrap_or("").trim().is_empty();
+
+                        if forced || !matches!(validate(&input), Validation::Incomplete) {
+                            self.finish(&mut stdout, buffer, "")?;
+                            return Ok(ReadLine::Input(input.trim_end().to_string()));
+                        }
+
+                        buffer.move_to_end();
+                        buffer.insert_char('\n');
+                        history_pos = None;
                     }
                     (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                        stdout.queue(Print("^C\r\n"))?;
-                        stdout.flush()?;
+                        self.finish(&mut stdout, buffer, "^C")?;
                         return Ok(ReadLine::Interrupted);
                     }
                     (KeyCode::Char('d'), KeyModifiers::CONTROL) if buffer.is_empty() => {
-                        stdout.queue(Print("\r\n"))?;
-                        stdout.flush()?;
+                        self.finish(&mut stdout, buffer, "")?;
                         return Ok(ReadLine::Eof);
                     }
+                    (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
+                        self.reverse_search(&mut stdout, buffer)?;
+                        history_pos = None;
+                    }
                     (KeyCode::Char('a'), KeyModifiers::CONTROL) | (KeyCode::Home, _) => {
                         buffer.move_home();
                     }
                     (KeyCode::Char('e'), KeyModifiers::CONTROL) | (KeyCode::End, _) => {
                         buffer.move_end();
                     }
-                    (KeyCode::Left, _) => {
+                    (KeyCode::Tab, _) => {
+                        if buffer.word_before_cursor().is_empty() {
+                            buffer.insert_text("    ");
+                        } else if let Completion::Ambiguous(matches) =
+                            completion::complete(buffer, &self.candidates)
+                        {
+                            self.menu = completion::menu_rows(&matches, self.columns);
+                        }
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('b'), KeyModifiers::ALT)
+                    | (KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                        buffer.move_word_left();
+                    }
+                    (KeyCode::Char('f'), KeyModifiers::ALT)
+                    | (KeyCode::Right, KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                        buffer.move_word_right();
+                    }
+                    (KeyCode::Char('b'), KeyModifiers::CONTROL) | (KeyCode::Left, _) => {
                         buffer.move_left();
                     }
-                    (KeyCode::Right, _) => {
+                    (KeyCode::Char('f'), KeyModifiers::CONTROL) | (KeyCode::Right, _) => {
                         buffer.move_right();
                     }
+                    (KeyCode::Char('k'), KeyModifiers::CONTROL) => {
+                        buffer.kill_to_end();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
+                        buffer.kill_to_start();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('w'), KeyModifiers::CONTROL) => {
+                        buffer.kill_word_before();
+                        history_pos = None;
+                    }
+                    (KeyCode::Backspace, KeyModifiers::ALT) => {
+                        buffer.kill_word_left();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('d'), KeyModifiers::ALT) => {
+                        buffer.kill_word_right();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('y'), KeyModifiers::CONTROL) => {
+                        buffer.yank();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('y'), KeyModifiers::ALT) => {
+                        buffer.yank_pop();
+                        history_pos = None;
+                    }
+                    (KeyCode::Char('t'), KeyModifiers::CONTROL) => {
+                        buffer.transpose();
+                        history_pos = None;
+                    }
+                    // Terminals report Ctrl-_ differently, so accept the common variants.
+                    (KeyCode::Char('_' | '/' | '7'), KeyModifiers::CONTROL) => {
+                        buffer.undo();
+                        history_pos = None;
+                    }
                     (KeyCode::Up, _) => {
-                        self.move_history(-1, &mut history_pos, &mut draft, &mut buffer);
-                        buffer.move_end();
+                        if !buffer.move_up() {
+                            self.move_history(-1, &mut history_pos, &mut draft, buffer);
+                            buffer.move_end();
+                        }
                     }
                     (KeyCode::Down, _) => {
-                        self.move_history(1, &mut history_pos, &mut draft, &mut buffer);
-                        buffer.move_end();
+                        if !buffer.move_down() {
+                            self.move_history(1, &mut history_pos, &mut draft, buffer);
+                            buffer.move_end();
+                        }
                     }
                     (KeyCode::Backspace, _) => {
                         buffer.backspace();
@@ -252,14 +534,19 @@ impl LineEditor {
                         buffer.insert_char(c);
                         history_pos = None;
                         self.ignore_next_submit = false;
-                        self.consume_queued_text(&mut buffer)?;
+                        self.consume_queued_text(buffer)?;
                     }
                     _ => {}
                 },
                 _ => {}
             }
 
-            self.render(&mut stdout, &buffer)?;
+            self.error = match validate(&buffer.text()) {
+                Validation::Invalid { span, message } => Some((span, message)),
+                _ => None,
+            };
+
+            self.render(&mut stdout, buffer)?;
         }
     }
 
@@ -285,6 +572,9 @@ impl LineEditor {
                     code: KeyCode::Enter,
                     ..
                 }) => buffer.insert_char('\n'),
+                Event::Key(KeyEvent {
+                    code: KeyCode::Tab, ..
+                }) => buffer.insert_char('\t'),
                 event => {
                     self.pending_events.push_back(event);
                     break;
@@ -299,11 +589,7 @@ impl LineEditor {
         if let Some(path) = &self.history_path {
             match fs::read_to_string(path) {
                 Ok(history) => {
-                    self.history = history
-                        .lines()
-                        .filter(|line| !line.trim().is_empty())
-                        .map(String::from)
-                        .collect();
+                    self.history = parse_history(&history);
                     self.truncate_history();
                 }
                 Err(e) if e.kind() == io::ErrorKind::NotFound => {}
@@ -318,7 +604,7 @@ impl LineEditor {
 
     fn save_history(&self) {
         if let Some(path) = &self.history_path {
-            if let Err(e) = fs::write(path, self.history.join("\n")) {
+            if let Err(e) = fs::write(path, format_history(&self.history)) {
                 eprintln!(
                     "{} failed to save history: {}",
                     "warning:".bright_yellow().bold(),
@@ -340,12 +626,14 @@ impl LineEditor {
     }
 
     fn truncate_history(&mut self) {
-        if self.history.len() > HISTORY_LIMIT {
-            let overflow = self.history.len() - HISTORY_LIMIT;
+        if self.history.len() > self.history_limit {
+            let overflow = self.history.len() - self.history_limit;
             self.history.drain(0..overflow);
         }
     }
 
+    /// Step through the history entries which start with the input typed before the
+    /// first step, so that an empty input steps through all of them.
     fn move_history(
         &self,
         direction: isize,
@@ -353,131 +641,330 @@ impl LineEditor {
         draft: &mut Vec<char>,
         buffer: &mut InputBuffer,
     ) {
-        if self.history.is_empty() {
-            return;
+        if history_pos.is_none() {
+            *draft = buffer.chars().to_vec();
         }
 
-        match (*history_pos, direction) {
-            (None, -1) => {
-                *draft = buffer.chars.clone();
-                *history_pos = Some(self.history.len() - 1);
+        let prefix: String = draft.iter().collect();
+        let matches = |entry: &String| entry.starts_with(&prefix) && *entry != prefix;
+
+        let next = match (*history_pos, direction) {
+            (None, -1) => self.history.iter().rposition(matches),
+            (Some(pos), -1) => match self.history[..pos].iter().rposition(matches) {
+                Some(next) => Some(next),
+                None => return,
+            },
+            (Some(pos), 1) => self.history[pos + 1..]
+                .iter()
+                .position(matches)
+                .map(|offset| pos + 1 + offset),
+            _ => return,
+        };
+
+        match next {
+            Some(pos) => {
+                *history_pos = Some(pos);
+                buffer.replace(self.history[pos].chars().collect());
             }
-            (Some(pos), -1) if pos > 0 => *history_pos = Some(pos - 1),
-            (Some(pos), 1) if pos + 1 < self.history.len() => *history_pos = Some(pos + 1),
-            (Some(_), 1) => {
+            None if history_pos.is_some() => {
                 *history_pos = None;
                 buffer.replace(draft.clone());
-                return;
             }
-            _ => return,
+            None => {}
         }
+    }
+
+    /// Search the history for entries containing a query typed character by character,
+    /// like Ctrl-R in shells. Accepting a match puts it into the buffer, and any key which
+    /// isn't part of the search is handled as usual afterwards.
+    fn reverse_search<W: Write>(&mut self, stdout: &mut W, buffer: &mut InputBuffer) -> Result<()> {
+        let mut query = String::new();
+        let mut found: Option<(usize, usize)> = None;
 
-        if let Some(pos) = *history_pos {
-            buffer.replace(self.history[pos].chars().collect());
+        loop {
+            self.render_search(stdout, &query, found)?;
+
+            let (code, modifiers) = match self.read_event()? {
+                Event::Key(KeyEvent {
+                    code, modifiers, ..
+                }) => (code, modifiers),
+                Event::Paste(pasted) => {
+                    query.push_str(&pasted.replace(['\r', '\n'], " "));
+                    found = self.search_history(&query, self.history.len());
+                    continue;
+                }
+                Event::Resize(columns, _) => {
+                    let (_, line) = self.search_line(&query, found);
+                    self.columns = columns as usize;
+                    self.cursor_row = Position::default().advance(&line, self.columns).row;
+                    continue;
+                }
+                _ => continue,
+            };
+
+            match (code, modifiers) {
+                (KeyCode::Char('r'), KeyModifiers::CONTROL) => {
+                    let before = found.map(|(index, _)| index).unwrap_or(self.history.len());
+                    found = self.search_history(&query, before).or(found);
+                }
+                (KeyCode::Char('g'), KeyModifiers::CONTROL) | (KeyCode::Esc, _) => break,
+                (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                    query.push(c);
+                    let before = found
+                        .map(|(index, _)| index + 1)
+                        .unwrap_or(self.history.len());
+                    found = self.search_history(&query, before);
+                }
+                (KeyCode::Backspace, _) => {
+                    query.pop();
+                    found = self.search_history(&query, self.history.len());
+                }
+                (code, modifiers) => {
+                    if let Some((index, _)) = found {
+                        buffer.replace(self.history[index].chars().collect());
+                    }
+
+                    self.pending_events
+                        .push_front(Event::Key(KeyEvent::new(code, modifiers)));
+                    break;
+                }
+            }
         }
+
+        Ok(())
     }
 
-    fn render<W: Write>(&mut self, stdout: &mut W, buffer: &InputBuffer) -> Result<()> {
-        let input = buffer.to_string();
-        let width = PROMPT_WIDTH + buffer.len();
-        let clear_width = self.rendered_width.max(width);
+    /// Find the newest history entry before the given index which contains the query,
+    /// returning its index and the byte offset of the match.
+    fn search_history(&self, query: &str, before: usize) -> Option<(usize, usize)> {
+        if query.is_empty() {
+            return None;
+        }
+
+        self.history[..before]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, entry)| entry.find(query).map(|offset| (index, offset)))
+    }
+
+    fn render_search<W: Write>(
+        &mut self,
+        stdout: &mut W,
+        query: &str,
+        found: Option<(usize, usize)>,
+    ) -> Result<()> {
+        let (styled, line) = self.search_line(query, found);
+        let end = Position::default().advance(&line, self.columns);
+
+        if self.cursor_row > 0 {
+            stdout.queue(cursor::MoveUp(self.cursor_row as u16))?;
+        }
 
         stdout
             .queue(cursor::MoveToColumn(0))?
-            .queue(Print(" ".repeat(clear_width)))?
-            .queue(cursor::MoveToColumn(0))?
-            .queue(Print(self.theme.prompt(PROMPT)))?
-            .queue(Print(highlight_mag(&input, &self.theme)))?
-            .queue(cursor::MoveToColumn(
-                (PROMPT_WIDTH + buffer.cursor()).min(u16::MAX as usize) as u16,
-            ))?;
+            .queue(terminal::Clear(ClearType::FromCursorDown))?
+            .queue(Print(styled))?;
 
-        self.rendered_width = width;
+        self.rendered_rows = end.row + 1;
+        self.cursor_row = end.row;
         stdout.flush()?;
         Ok(())
     }
-}
 
-#[derive(Clone, Default)]
-struct InputBuffer {
-    chars: Vec<char>,
-    cursor: usize,
-}
+    /// The row shown while searching the history, both with the match highlighted and as
+    /// plain text.
+    fn search_line(&self, query: &str, found: Option<(usize, usize)>) -> (String, String) {
+        let prompt = match (query.is_empty(), found) {
+            (false, None) => "(failed reverse-i-search)",
+            _ => "(reverse-i-search)",
+        };
+
+        // Multi-line entries are shown on a single row, with spaces for line breaks.
+        let (styled, plain) = match found {
+            Some((index, offset)) => {
+                let entry = self.history[index].replace('\n', " ");
+                let end = offset + query.len();
+
+                let styled = format!(
+                    "{}{}{}",
+                    &entry[..offset],
+                    self.theme.search_match(&entry[offset..end]),
+                    &entry[end..]
+                );
 
-impl InputBuffer {
-    fn new() -> Self {
-        Self::default()
-    }
+                (styled, entry)
+            }
+            None => (String::new(), String::new()),
+        };
 
-    fn len(&self) -> usize {
-        self.chars.len()
+        (
+            format!("{}`{}': {}", prompt, query, styled),
+            format!("{}`{}': {}", prompt, query, plain),
+        )
     }
 
-    fn cursor(&self) -> usize {
-        self.cursor
-    }
+    /// Find where each line of the input ends on the terminal and where the cursor is,
+    /// taking the width of every character and wrapping at the terminal width into account.
+    fn layout(&self, buffer: &InputBuffer) -> InputLayout {
+        let (cursor_line, cursor_column) = buffer.cursor_position();
+        let mut line_ends = vec![];
+        let mut cursor = Position::default();
+        let mut start = Position::default();
+
+        for (index, line) in buffer.text().split('\n').enumerate() {
+            let prompt = if index == 0 {
+                &self.prompt
+            } else {
+                &self.continuation_prompt
+            };
+            let after_prompt = start.advance(prompt, self.columns);
 
-    fn is_empty(&self) -> bool {
-        self.chars.is_empty()
-    }
+            if index == cursor_line {
+                let before: String = line.chars().take(cursor_column).collect();
+                cursor = after_prompt.advance(&before, self.columns);
 
-    fn to_string(&self) -> String {
-        self.chars.iter().collect()
-    }
+                // Show the cursor where the character under it is drawn.
+                if let Some(next) = line.chars().nth(cursor_column) {
+                    cursor = cursor.wrap_before(next, self.columns);
+                }
+            }
 
-    fn replace(&mut self, chars: Vec<char>) {
-        self.chars = chars;
-        self.cursor = self.chars.len();
-    }
+            let end = after_prompt.advance(line, self.columns);
+            line_ends.push(end);
+            start = end.next_row();
+        }
 
-    fn clear(&mut self) {
-        self.chars.clear();
-        self.cursor = 0;
+        InputLayout { line_ends, cursor }
     }
 
-    fn insert_char(&mut self, ch: char) {
-        if let Some(ch) = normalize_pasted_char(ch) {
-            self.chars.insert(self.cursor, ch);
-            self.cursor += 1;
+    fn render<W: Write>(&mut self, stdout: &mut W, buffer: &InputBuffer) -> Result<()> {
+        let input = buffer.text();
+        let error = self.error.as_ref().map(|(span, _)| *span);
+        let lines = highlight::highlight_lines(&input, &self.theme, error);
+        let layout = self.layout(buffer);
+
+        if self.cursor_row > 0 {
+            stdout.queue(cursor::MoveUp(self.cursor_row as u16))?;
         }
-    }
 
-    fn insert_text(&mut self, text: &str) {
-        for ch in text.chars() {
-            self.insert_char(ch);
+        stdout
+            .queue(cursor::MoveToColumn(0))?
+            .queue(terminal::Clear(ClearType::FromCursorDown))?;
+
+        for (index, line) in lines.iter().enumerate() {
+            let prompt = if index == 0 {
+                &self.prompt
+            } else {
+                &self.continuation_prompt
+            };
+
+            if index > 0 {
+                stdout.queue(Print("\r\n"))?;
+            }
+
+            stdout
+                .queue(Print(self.theme.prompt(prompt)))?
+                .queue(Print(line))?;
         }
-    }
 
-    fn backspace(&mut self) {
-        if self.cursor > 0 {
-            self.cursor -= 1;
-            self.chars.remove(self.cursor);
+        let mut end = layout.end();
+
+        if let Some((_, message)) = &self.error {
+            let hint = format!("{}{}", " ".repeat(display_width(&self.prompt)), message);
+            stdout.queue(Print(format!("\r\n{}", self.theme.hint(&hint))))?;
+            end = end.next_row().advance(&hint, self.columns);
         }
-    }
 
-    fn delete(&mut self) {
-        if self.cursor < self.chars.len() {
-            self.chars.remove(self.cursor);
+        for menu_row in &self.menu {
+            stdout.queue(Print(format!("\r\n{}", menu_row)))?;
+            end = end.next_row().advance(menu_row, self.columns);
         }
-    }
 
-    fn move_home(&mut self) {
-        self.cursor = 0;
-    }
+        let rows_below = end.row - layout.cursor.row;
+        if rows_below > 0 {
+            stdout.queue(cursor::MoveUp(rows_below as u16))?;
+        }
 
-    fn move_end(&mut self) {
-        self.cursor = self.chars.len();
+        stdout.queue(cursor::MoveToColumn(
+            layout.cursor.display_column(self.columns),
+        ))?;
+
+        self.rendered_rows = end.row + 1;
+        self.cursor_row = layout.cursor.row;
+        stdout.flush()?;
+        Ok(())
     }
 
-    fn move_left(&mut self) {
-        self.cursor = self.cursor.saturating_sub(1);
+    /// Move the cursor past the end of the input, printing a marker like `^C` after it.
+    fn finish<W: Write>(
+        &mut self,
+        stdout: &mut W,
+        buffer: &InputBuffer,
+        marker: &str,
+    ) -> Result<()> {
+        // Redraw first so that a completion menu or error hint disappears.
+        self.menu.clear();
+        self.error = None;
+        self.render(stdout, buffer)?;
+
+        let rows_below = self.rendered_rows - 1 - self.cursor_row;
+        if rows_below > 0 {
+            stdout.queue(cursor::MoveDown(rows_below as u16))?;
+        }
+
+        let end = self.layout(buffer).end();
+
+        stdout
+            .queue(cursor::MoveToColumn(end.display_column(self.columns)))?
+            .queue(Print(format!("{}\r\n", marker)))?;
+        stdout.flush()?;
+        Ok(())
     }
+}
+
+/// Parse the history file, where every line of a multi-line entry except the last one
+/// ends with a backslash. Backslashes at the end of a line of the entry itself are
+/// doubled, so only an odd number of them continues the entry.
+fn parse_history(history: &str) -> Vec<String> {
+    let mut entries = vec![];
+    let mut entry = String::new();
+
+    for line in history.lines() {
+        let text = line.trim_end_matches('\\');
+        let backslashes = line.len() - text.len();
+
+        entry.push_str(text);
+        entry.push_str(&"\\".repeat(backslashes / 2));
 
-    fn move_right(&mut self) {
-        if self.cursor < self.chars.len() {
-            self.cursor += 1;
+        if backslashes % 2 == 1 {
+            entry.push('\n');
+        } else {
+            if !entry.trim().is_empty() {
+                entries.push(entry.clone());
+            }
+            entry.clear();
         }
     }
+
+    entries
+}
+
+fn format_history(history: &[String]) -> String {
+    history
+        .iter()
+        .map(|entry| {
+            entry
+                .split('\n')
+                .map(|line| {
+                    let text = line.trim_end_matches('\\');
+                    format!("{}{}", line, &line[text.len()..])
+                })
+                .collect::<Vec<_>>()
+                .join("\\\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
 struct RawMode;
@@ -499,191 +986,114 @@ impl Drop for RawMode {
 
 fn normalize_pasted_char(ch: char) -> Option<char> {
     match ch {
-        '\r' | '\n' | '\t' => Some(' '),
+        '\r' | '\n' => Some('\n'),
+        '\t' => Some(' '),
         ch if ch.is_control() => None,
         ch => Some(ch),
     }
 }
 
-#[derive(Clone, Copy)]
-struct ReplTheme {
-    prompt: &'static str,
-    keyword: &'static str,
-    type_name: &'static str,
-    string: &'static str,
-    number: &'static str,
-    punctuation: &'static str,
-    operator: &'static str,
-}
-
-impl ReplTheme {
-    fn from_env() -> Self {
-        match std::env::var("MAG_REPL_THEME").as_deref() {
-            Ok("mono") | Ok("plain") => Self::mono(),
-            _ => Self::mag(),
+/// Format a value the way it would be written in Mag, colored like highlighted source.
+fn format_value(value: &Value, theme: &ReplTheme) -> String {
+    match value {
+        Value::Empty => String::new(),
+        Value::Bool(value) => theme.keyword(&value.to_string()),
+        Value::Int(_) | Value::UInt(_) | Value::Float(_) => theme.number(&value.to_string()),
+        Value::String(value) => theme.string(&quote_string(value)),
+        Value::List(values) => {
+            let items: Vec<String> = values
+                .iter()
+                .map(|value| format_value(value, theme))
+                .collect();
+
+            format!(
+                "{}{}{}",
+                theme.punctuation("["),
+                items.join(&theme.punctuation(", ")),
+                theme.punctuation("]")
+            )
         }
+        Value::Other(value) => theme.value(value),
     }
+}
 
-    fn mag() -> Self {
-        Self {
-            prompt: "\x1b[1;38;5;120m",
-            keyword: "\x1b[1;38;5;75m",
-            type_name: "\x1b[1;38;5;207m",
-            string: "\x1b[1;38;5;214m",
-            number: "\x1b[1;38;5;214m",
-            punctuation: "\x1b[1;38;5;111m",
-            operator: "\x1b[1;38;5;210m",
-        }
-    }
+fn is_identifier_continue(ch: char) -> bool {
+    ch == '_' || ch.is_ascii_alphanumeric()
+}
 
-    fn mono() -> Self {
-        Self {
-            prompt: "",
-            keyword: "",
-            type_name: "",
-            string: "",
-            number: "",
-            punctuation: "",
-            operator: "",
-        }
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn prompt(&self, text: &str) -> String {
-        self.paint(self.prompt, text)
+    #[test]
+    fn counts_open_brackets_and_blocks() {
+        assert!(has_open_blocks("def twice(n Int) do"));
+        assert!(has_open_blocks("[1, (2"));
+        assert!(!has_open_blocks("match x\n  1 => 2\nend"));
+        assert!(!has_open_blocks("f(x))"));
     }
 
-    fn keyword(&self, text: &str) -> String {
-        self.paint(self.keyword, text)
+    #[test]
+    fn ignores_brackets_and_blocks_in_strings_and_comments() {
+        assert!(!has_open_blocks("\"do (\""));
+        assert!(!has_open_blocks("x // do ("));
+        // Strings don't have escapes, so the backslash doesn't keep the string open.
+        assert!(!has_open_blocks("f(\"\\\")"));
     }
 
-    fn type_name(&self, text: &str) -> String {
-        self.paint(self.type_name, text)
+    #[test]
+    fn lexes_input_ending_in_an_operator() {
+        for (input, kind) in [("1 /", TokenKind::Slash), ("1 +", TokenKind::Plus)] {
+            let tokens = Runtime::tokenize(input);
+            assert_eq!(tokens.last().map(|token| &token.kind), Some(&kind));
+        }
     }
 
-    fn string(&self, text: &str) -> String {
-        self.paint(self.string, text)
+    #[test]
+    fn reloads_history_entries_ending_in_backslashes() {
+        let history = vec![
+            "1 \\".to_string(),
+            "1 +\n2\\\\".to_string(),
+            "x".to_string(),
+        ];
+        let formatted = format_history(&history);
+
+        assert_eq!(formatted, "1 \\\\\n1 +\\\n2\\\\\\\\\nx");
+        assert_eq!(parse_history(&formatted), history);
     }
 
-    fn number(&self, text: &str) -> String {
-        self.paint(self.number, text)
+    #[test]
+    fn reads_multi_line_history_entries() {
+        assert_eq!(
+            parse_history("def f() do\\\n  1\\\nend\nf()\n"),
+            ["def f() do\n  1\nend", "f()"]
+        );
     }
 
-    fn punctuation(&self, text: &str) -> String {
-        self.paint(self.punctuation, text)
-    }
+    #[test]
+    fn prints_values_like_the_library() {
+        let theme = ReplTheme::named("mono");
+        let value = Value::List(vec![Value::from("tab\tand \"quotes\""), Value::Int(1)]);
 
-    fn operator(&self, text: &str) -> String {
-        self.paint(self.operator, text)
+        assert_eq!(
+            format_value(&value, &theme),
+            "[\"tab\\tand \\\"quotes\\\"\", 1]"
+        );
+        assert_eq!(format_value(&value, &theme), value.to_string());
     }
 
-    fn paint(&self, style: &str, text: &str) -> String {
-        if style.is_empty() {
-            text.to_string()
-        } else {
-            format!("{}{}\x1b[0m", style, text)
-        }
-    }
-}
-
-fn highlight_mag(input: &str, theme: &ReplTheme) -> String {
-    let mut highlighted = String::new();
-    let chars: Vec<char> = input.chars().collect();
-    let mut index = 0;
-
-    while index < chars.len() {
-        let ch = chars[index];
-
-        if ch == '"' {
-            let start = index;
-            index += 1;
-
-            while index < chars.len() {
-                let current = chars[index];
-                index += 1;
-
-                if current == '"' {
-                    break;
-                }
-            }
-
-            let text = chars[start..index].iter().collect::<String>();
-            highlighted.push_str(&theme.string(&text));
-        } else if ch.is_ascii_digit() {
-            let start = index;
-            index += 1;
-
-            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
-                index += 1;
-            }
-
-            let text = chars[start..index].iter().collect::<String>();
-            highlighted.push_str(&theme.number(&text));
-        } else if is_identifier_start(ch) {
-            let start = index;
-            index += 1;
+    #[test]
+    fn offers_keywords_the_lexer_knows() {
+        let keywords: Vec<String> = keywords().collect();
 
-            while index < chars.len() && is_identifier_continue(chars[index]) {
-                index += 1;
-            }
-
-            let word: String = chars[start..index].iter().collect();
-            if is_keyword(&word) {
-                highlighted.push_str(&theme.keyword(&word));
-            } else if word.chars().next().map(char::is_uppercase).unwrap_or(false) {
-                highlighted.push_str(&theme.type_name(&word));
-            } else {
-                highlighted.push_str(&word);
-            }
-        } else if "()[]{}.,:".contains(ch) {
-            highlighted.push_str(&theme.punctuation(&ch.to_string()));
-            index += 1;
-        } else if "+-*/%=!<>^".contains(ch) {
-            highlighted.push_str(&theme.operator(&ch.to_string()));
-            index += 1;
-        } else {
-            highlighted.push(ch);
-            index += 1;
+        for keyword in ["const", "do", "end", "import", "match", "var", "true"] {
+            assert!(keywords.iter().any(|word| word == keyword), "{}", keyword);
         }
     }
 
-    highlighted
-}
-
-fn is_identifier_start(ch: char) -> bool {
-    ch == '_' || ch.is_ascii_alphabetic()
-}
-
-fn is_identifier_continue(ch: char) -> bool {
-    ch == '_' || ch.is_ascii_alphanumeric()
-}
-
-fn is_keyword(word: &str) -> bool {
-    matches!(
-        word,
-        "and"
-            | "as"
-            | "catch"
-            | "case"
-            | "const"
-            | "def"
-            | "do"
-            | "else"
-            | "end"
-            | "enum"
-            | "false"
-            | "for"
-            | "if"
-            | "import"
-            | "interface"
-            | "it"
-            | "match"
-            | "or"
-            | "return"
-            | "then"
-            | "this"
-            | "true"
-            | "var"
-            | "while"
-            | "with"
-    )
+    #[test]
+    fn counts_token_positions_in_characters() {
+        let tokens = Runtime::tokenize("\"e\u{301}\" x");
+        assert_eq!((tokens[1].start_pos, tokens[1].end_pos), (5, 6));
+    }
 }
@@ -0,0 +1,389 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use colored::*;
+use serde::Deserialize;
+
+use crate::config;
+
+/// The colors used by the REPL, stored as the escape sequences which start each style.
+/// An empty sequence leaves the text unstyled.
+#[derive(Clone)]
+pub(super) struct ReplTheme {
+    prompt: String,
+    keyword: String,
+    type_name: String,
+    string: String,
+    number: String,
+    punctuation: String,
+    operator: String,
+    comment: String,
+    error: String,
+    hint: String,
+    value: String,
+    search_match: String,
+}
+
+/// A theme file like `~/.config/mag/themes/ocean.toml`, where every color is optional and
+/// defaults to the built-in `mag` theme:
+///
+/// ```toml
+/// prompt = "bold green"
+/// keyword = "bold #5fafff"
+/// string = "214"
+/// comment = "dim italic"
+/// ```
+///
+/// A color is a list of words, each of which is either a modifier (`bold`, `dim`,
+/// `italic` or `underline`), a color name like `red` or `bright-blue`, an index into the
+/// 256-color palette or a hex color like `#ff8700`. Hex colors are shown as the closest
+/// palette color unless the terminal supports true colors.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    prompt: Option<String>,
+    keyword: Option<String>,
+    type_name: Option<String>,
+    string: Option<String>,
+    number: Option<String>,
+    punctuation: Option<String>,
+    operator: Option<String>,
+    comment: Option<String>,
+    error: Option<String>,
+    hint: Option<String>,
+    value: Option<String>,
+    search_match: Option<String>,
+}
+
+/// An error which occurred while loading a theme file.
+#[derive(Debug)]
+pub(super) enum ThemeError {
+    NotFound(String),
+    Io(PathBuf, io::Error),
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(name) => write!(f, "theme `{}` not found", name),
+            Self::Io(path, e) => write!(f, "failed to read '{}': {}", path.display(), e),
+            Self::Invalid(path, e) => write!(f, "invalid theme '{}': {}", path.display(), e),
+        }
+    }
+}
+
+impl ReplTheme {
+    /// Load a built-in theme or one from the themes directory by name, falling back to
+    /// the default theme with a warning if it can't be loaded.
+    ///
+    /// Colors are disabled completely if `NO_COLOR` is set or the terminal doesn't
+    /// support 256 colors.
+    pub(super) fn named(name: &str) -> Self {
+        if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) || !supports_256_colors()
+        {
+            return Self::mono();
+        }
+
+        match name {
+            "mag" => Self::mag(),
+            "mono" | "plain" => Self::mono(),
+            name => Self::load(name).unwrap_or_else(|e| {
+                eprintln!("{} {}", "warning:".bright_yellow().bold(), e);
+                Self::mag()
+            }),
+        }
+    }
+
+    /// Load the theme with the given name from the themes directory.
+    fn load(name: &str) -> Result<Self, ThemeError> {
+        let path = themes_dir()
+            .map(|directory| directory.join(name).with_extension("toml"))
+            .filter(|path| path.is_file())
+            .ok_or_else(|| ThemeError::NotFound(name.to_string()))?;
+
+        Self::from_file(&path)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ThemeError> {
+        let text = fs::read_to_string(path).map_err(|e| ThemeError::Io(path.to_path_buf(), e))?;
+        let file: ThemeFile = toml::from_str(&text)
+            .map_err(|e| ThemeError::Invalid(path.to_path_buf(), e.to_string()))?;
+
+        let default = Self::mag();
+        let truecolor = supports_truecolor();
+        let color = |spec: Option<String>, default: String| match spec {
+            Some(spec) => parse_color(&spec, truecolor)
+                .map_err(|e| ThemeError::Invalid(path.to_path_buf(), e)),
+            None => Ok(default),
+        };
+
+        Ok(Self {
+            prompt: color(file.prompt, default.prompt)?,
+            keyword: color(file.keyword, default.keyword)?,
+            type_name: color(file.type_name, default.type_name)?,
+            string: color(file.string, default.string)?,
+            number: color(file.number, default.number)?,
+            punctuation: color(file.punctuation, default.punctuation)?,
+            operator: color(file.operator, default.operator)?,
+            comment: color(file.comment, default.comment)?,
+            error: color(file.error, default.error)?,
+            hint: color(file.hint, default.hint)?,
+            value: color(file.value, default.value)?,
+            search_match: color(file.search_match, default.search_match)?,
+        })
+    }
+
+    fn mag() -> Self {
+        Self {
+            prompt: "\x1b[1;38;5;120m".to_string(),
+            keyword: "\x1b[1;38;5;75m".to_string(),
+            type_name: "\x1b[1;38;5;207m".to_string(),
+            string: "\x1b[1;38;5;214m".to_string(),
+            number: "\x1b[1;38;5;214m".to_string(),
+            punctuation: "\x1b[1;38;5;111m".to_string(),
+            operator: "\x1b[1;38;5;210m".to_string(),
+            comment: "\x1b[38;5;244m".to_string(),
+            error: "\x1b[4;38;5;203m".to_string(),
+            hint: "\x1b[2m".to_string(),
+            value: String::new(),
+            search_match: "\x1b[1;4;38;5;120m".to_string(),
+        }
+    }
+
+    fn mono() -> Self {
+        Self {
+            prompt: String::new(),
+            keyword: String::new(),
+            type_name: String::new(),
+            string: String::new(),
+            number: String::new(),
+            punctuation: String::new(),
+            operator: String::new(),
+            comment: String::new(),
+            error: String::new(),
+            hint: String::new(),
+            value: String::new(),
+            search_match: String::new(),
+        }
+    }
+
+    pub(super) fn prompt(&self, text: &str) -> String {
+        self.paint(&self.prompt, text)
+    }
+
+    pub(super) fn keyword(&self, text: &str) -> String {
+        self.paint(&self.keyword, text)
+    }
+
+    pub(super) fn type_name(&self, text: &str) -> String {
+        self.paint(&self.type_name, text)
+    }
+
+    pub(super) fn string(&self, text: &str) -> String {
+        self.paint(&self.string, text)
+    }
+
+    pub(super) fn number(&self, text: &str) -> String {
+        self.paint(&self.number, text)
+    }
+
+    pub(super) fn punctuation(&self, text: &str) -> String {
+        self.paint(&self.punctuation, text)
+    }
+
+    pub(super) fn operator(&self, text: &str) -> String {
+        self.paint(&self.operator, text)
+    }
+
+    pub(super) fn comment(&self, text: &str) -> String {
+        self.paint(&self.comment, text)
+    }
+
+    pub(super) fn error(&self, text: &str) -> String {
+        self.paint(&self.error, text)
+    }
+
+    pub(super) fn hint(&self, text: &str) -> String {
+        self.paint(&self.hint, text)
+    }
+
+    pub(super) fn value(&self, text: &str) -> String {
+        self.paint(&self.value, text)
+    }
+
+    pub(super) fn search_match(&self, text: &str) -> String {
+        self.paint(&self.search_match, text)
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if style.is_empty() {
+            text.to_string()
+        } else {
+            format!("{}{}\x1b[0m", style, text)
+        }
+    }
+}
+
+/// The directory user themes are loaded from, next to the configuration file.
+fn themes_dir() -> Option<PathBuf> {
+    config::config_dir().map(|directory| directory.join("themes"))
+}
+
+/// Guess whether the terminal can show the 256-color palette the themes are made of.
+fn supports_256_colors() -> bool {
+    if supports_truecolor() {
+        return true;
+    }
+
+    match env::var("TERM") {
+        Ok(term) => term.contains("256color") || term.contains("direct"),
+        // Windows terminals don't set TERM, but all current ones support 256 colors.
+        Err(_) => cfg!(windows),
+    }
+}
+
+/// Check whether the terminal reports support for 24-bit colors.
+fn supports_truecolor() -> bool {
+    matches!(
+        env::var("COLORTERM").as_deref(),
+        Ok("truecolor") | Ok("24bit")
+    )
+}
+
+/// Translate a color specification like `bold #ff8700` into an escape sequence, using
+/// 24-bit colors for hex colors only if the terminal supports them.
+fn parse_color(spec: &str, truecolor: bool) -> Result<String, String> {
+    let mut codes = vec![];
+
+    for word in spec.split_whitespace() {
+        let code = match word.to_lowercase().as_str() {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            "black" => "30".to_string(),
+            "red" => "31".to_string(),
+            "green" => "32".to_string(),
+            "yellow" => "33".to_string(),
+            "blue" => "34".to_string(),
+            "magenta" => "35".to_string(),
+            "cyan" => "36".to_string(),
+            "white" => "37".to_string(),
+            "bright-black" | "gray" | "grey" => "90".to_string(),
+            "bright-red" => "91".to_string(),
+            "bright-green" => "92".to_string(),
+            "bright-yellow" => "93".to_string(),
+            "bright-blue" => "94".to_string(),
+            "bright-magenta" => "95".to_string(),
+            "bright-cyan" => "96".to_string(),
+            "bright-white" => "97".to_string(),
+            hex if hex.starts_with('#') && hex.len() == 7 && hex.is_ascii() => {
+                let channel = |range| u8::from_str_radix(&hex[range], 16);
+
+                match (channel(1..3), channel(3..5), channel(5..7)) {
+                    (Ok(r), Ok(g), Ok(b)) if truecolor => format!("38;2;{};{};{}", r, g, b),
+                    (Ok(r), Ok(g), Ok(b)) => format!("38;5;{}", nearest_palette_color(r, g, b)),
+                    _ => return Err(format!("invalid hex color `{}`", word)),
+                }
+            }
+            index => match index.parse::<u8>() {
+                Ok(index) => format!("38;5;{}", index),
+                Err(_) => return Err(format!("unknown color or modifier `{}`", word)),
+            },
+        };
+
+        codes.push(code);
+    }
+
+    if codes.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("\x1b[{}m", codes.join(";")))
+    }
+}
+
+/// Find the color of the 256-color palette closest to the given one, which is either
+/// part of the 6x6x6 color cube starting at 16 or of the gray ramp starting at 232.
+fn nearest_palette_color(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    let level = |channel: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&index| LEVELS[index].abs_diff(channel))
+            .unwrap_or(0)
+    };
+    let distance = |(r2, g2, b2): (u8, u8, u8)| {
+        [(r, r2), (g, g2), (b, b2)]
+            .iter()
+            .map(|&(x, y)| u32::from(x.abs_diff(y)).pow(2))
+            .sum::<u32>()
+    };
+
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = 16 + 36 * ri + 6 * gi + bi;
+    let cube_distance = distance((LEVELS[ri], LEVELS[gi], LEVELS[bi]));
+
+    // The grays run from 8 to 238 in steps of 10.
+    let average = (u32::from(r) + u32::from(g) + u32::from(b)) / 3;
+    let gray = (average.saturating_sub(3) / 10).min(23);
+    let shade = (8 + 10 * gray) as u8;
+    let gray_distance = distance((shade, shade, shade));
+
+    if gray_distance < cube_distance {
+        232 + gray as u8
+    } else {
+        cube as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_modifiers_and_indices() {
+        assert_eq!(parse_color("bold red", false), Ok("\x1b[1;31m".to_string()));
+        assert_eq!(
+            parse_color("Dim Bright-Cyan", false),
+            Ok("\x1b[2;96m".to_string())
+        );
+        assert_eq!(parse_color("208", false), Ok("\x1b[38;5;208m".to_string()));
+        assert_eq!(parse_color("  ", false), Ok(String::new()));
+    }
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(
+            parse_color("underline #ff8700", true),
+            Ok("\x1b[4;38;2;255;135;0m".to_string())
+        );
+        assert!(parse_color("#ff87zz", true).is_err());
+        assert!(parse_color("#ff87", true).is_err());
+        assert!(parse_color("#aébcd", true).is_err());
+    }
+
+    #[test]
+    fn maps_hex_colors_to_the_palette_without_truecolor() {
+        assert_eq!(
+            parse_color("underline #ff8700", false),
+            Ok("\x1b[4;38;5;208m".to_string())
+        );
+        assert_eq!(nearest_palette_color(0x5f, 0xaf, 0xff), 75);
+        assert_eq!(nearest_palette_color(0x80, 0x80, 0x80), 244);
+        assert_eq!(nearest_palette_color(0, 0, 0), 16);
+        assert_eq!(nearest_palette_color(255, 255, 255), 231);
+    }
+
+    #[test]
+    fn rejects_unknown_words() {
+        assert_eq!(
+            parse_color("bold sparkly", false),
+            Err("unknown color or modifier `sparkly`".to_string())
+        );
+        assert!(parse_color("256", false).is_err());
+    }
+}
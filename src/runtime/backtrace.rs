@@ -0,0 +1,95 @@
+use std::fmt;
+
+use super::Location;
+
+/// A single entry in a Mag-level backtrace.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The address of the instruction being executed in this frame.
+    pub address: usize,
+    /// The multimethod this frame belongs to, or `None` for top-level code.
+    pub method: Option<MethodFrame>,
+    /// The expression containing the instruction, if it could be found in the source map.
+    pub location: Option<Location>,
+}
+
+/// The multimethod implementation a frame is executing.
+#[derive(Clone, Debug)]
+pub struct MethodFrame {
+    pub name: String,
+    /// The pattern of the implementation which was matched by the call, in Mag syntax.
+    pub pattern: String,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.method {
+            Some(method) => write!(f, "{}({})", method.name, method.pattern)?,
+            None => write!(f, "<top level>")?,
+        }
+
+        match &self.location {
+            Some(location) => {
+                let (line, column) = location.line_column();
+                write!(
+                    f,
+                    " at {}:{}:{}",
+                    location.source.name,
+                    line + 1,
+                    column + 1
+                )
+            }
+            None => write!(f, " at address {}", self.address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Runtime, RuntimeConfig, RuntimeError, Source, Span};
+    use std::sync::Arc;
+
+    #[test]
+    fn shows_the_method_and_position() {
+        let source = Arc::new(Source::new("<test>", "1\n  fib(2)"));
+        let frame = Frame {
+            address: 7,
+            method: Some(MethodFrame {
+                name: "fib".to_string(),
+                pattern: "Int".to_string(),
+            }),
+            location: Some(Location::new(source, Span::new(4, 10))),
+        };
+        let top_level = Frame {
+            address: 7,
+            method: None,
+            location: None,
+        };
+
+        assert_eq!(frame.to_string(), "fib(Int) at <test>:2:3");
+        assert_eq!(top_level.to_string(), "<top level> at address 7");
+    }
+
+    #[test]
+    fn lists_calls_innermost_first() {
+        let mut runtime = Runtime::new(RuntimeConfig::default()).unwrap();
+        let result =
+            runtime.eval("def inner(n Int) get([1], n)\ndef outer(n Int) inner(n)\nouter(5)");
+
+        match result {
+            Err(RuntimeError::Native { backtrace, .. }) => {
+                let methods: Vec<&str> = backtrace
+                    .iter()
+                    .filter_map(|frame| frame.method.as_ref())
+                    .map(|method| method.name.as_str())
+                    .filter(|name| ["inner", "outer"].contains(name))
+                    .collect();
+
+                assert_eq!(methods, ["inner", "outer"]);
+                assert!(backtrace.last().is_some_and(|frame| frame.method.is_none()));
+            }
+            result => panic!("expected an error with a backtrace, got {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,792 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+use strontium::machine::instruction::{
+    BitwiseMethod, CalculationMethod, ComparisonMethod, DispatchPattern, Interrupt, InterruptKind,
+};
+use strontium::machine::register::{RegisterType, RegisterValue};
+use strontium::Instruction;
+
+use super::link;
+
+/// The bytes every bytecode file starts with.
+pub const MAGIC: &[u8; 4] = b"MAGB";
+
+/// The version of the bytecode format written by this build. Files with any other
+/// version are rejected, since instructions and patterns may be laid out differently.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The length of the header: the magic bytes, the format version and a flag which
+/// records whether the program was built with the prelude.
+const HEADER_LENGTH: usize = 9;
+
+/// A multimethod implementation and the address of its entry point.
+#[derive(Clone, Debug)]
+pub struct MethodEntry {
+    pub name: String,
+    pub pattern: DispatchPattern,
+    pub address: usize,
+}
+
+/// A compiled program which can be written to disk and executed later without
+/// recompiling it from source.
+pub struct BytecodeFile {
+    /// The multimethods to register in the VM's dispatch table before execution.
+    pub methods: Vec<MethodEntry>,
+    pub instructions: Vec<Instruction>,
+    /// Whether the program was built with the prelude, whose methods its code may call
+    /// and whose code it contains. It can only run in a runtime which agrees.
+    pub prelude: bool,
+}
+
+impl BytecodeFile {
+    /// Returns true if the given bytes look like a bytecode file rather than source code.
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), BytecodeFileError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        writer.write_all(&[u8::from(self.prelude)])?;
+
+        let contents = Contents {
+            methods: self.methods.iter().map(MethodRecord::from).collect(),
+            instructions: self
+                .instructions
+                .iter()
+                .map(InstructionRecord::try_from)
+                .collect::<Result<_, _>>()?,
+        };
+
+        bincode::serialize_into(writer, &contents)
+            .map_err(|e| BytecodeFileError::Corrupt(e.to_string()))
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Self, BytecodeFileError> {
+        if !Self::is_bytecode(bytes) || bytes.len() < HEADER_LENGTH {
+            return Err(BytecodeFileError::NotBytecode);
+        }
+
+        let mut version = [0; 4];
+        version.copy_from_slice(&bytes[4..8]);
+        let version = u32::from_le_bytes(version);
+
+        if version != FORMAT_VERSION {
+            return Err(BytecodeFileError::UnsupportedVersion(version));
+        }
+
+        let prelude = match bytes[8] {
+            0 => false,
+            1 => true,
+            flag => return Err(corrupt(format!("unknown prelude flag {}", flag))),
+        };
+
+        let mut body = &bytes[HEADER_LENGTH..];
+        let contents: Contents = bincode::deserialize_from(&mut body)
+            .map_err(|e| BytecodeFileError::Corrupt(e.to_string()))?;
+
+        if !body.is_empty() {
+            return Err(corrupt(format!(
+                "{} unexpected bytes after the program",
+                body.len()
+            )));
+        }
+
+        let file = Self {
+            methods: contents
+                .methods
+                .into_iter()
+                .map(MethodEntry::try_from)
+                .collect::<Result<_, _>>()?,
+            instructions: contents
+                .instructions
+                .into_iter()
+                .map(Instruction::try_from)
+                .collect::<Result<_, _>>()?,
+            prelude,
+        };
+        file.check_addresses()?;
+
+        Ok(file)
+    }
+
+    /// Make sure every method entry point and jump destination is the start of one of
+    /// the instructions, so the machine can't be sent outside of the program. Jumps may
+    /// also go to the end of the program, which finishes it.
+    fn check_addresses(&self) -> Result<(), BytecodeFileError> {
+        let mut starts = HashSet::new();
+        let mut end = 0;
+
+        for instruction in &self.instructions {
+            starts.insert(end);
+            end += link::encoded_size(instruction);
+        }
+
+        for method in &self.methods {
+            if !starts.contains(&method.address) {
+                return Err(corrupt(format!(
+                    "method `{}` starts at {}, which isn't the start of an instruction",
+                    method.name, method.address
+                )));
+            }
+        }
+
+        for instruction in &self.instructions {
+            let destination = match instruction {
+                Instruction::Jump { destination } | Instruction::JumpC { destination, .. } => {
+                    *destination as usize
+                }
+                _ => continue,
+            };
+
+            if destination != end && !starts.contains(&destination) {
+                return Err(corrupt(format!(
+                    "jump to {}, which isn't the start of an instruction",
+                    destination
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An error raised while reading or writing a bytecode file.
+#[derive(Debug)]
+pub enum BytecodeFileError {
+    Io(io::Error),
+    /// The file doesn't start with the bytecode header.
+    NotBytecode,
+    /// The file was written with a different version of the bytecode format.
+    UnsupportedVersion(u32),
+    /// The contents of the file couldn't be encoded or decoded.
+    Corrupt(String),
+    /// The program still contains label instructions, which only exist until a compiled
+    /// source is linked and have no encoding of their own.
+    UnresolvedLabel,
+}
+
+impl fmt::Display for BytecodeFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::NotBytecode => write!(f, "not a Mag bytecode file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode format version {} (expected {})",
+                version, FORMAT_VERSION
+            ),
+            Self::Corrupt(e) => write!(f, "corrupt bytecode file: {}", e),
+            Self::UnresolvedLabel => write!(f, "the program contains an unresolved label"),
+        }
+    }
+}
+
+impl Error for BytecodeFileError {}
+
+impl From<io::Error> for BytecodeFileError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+// The types of the compiler and the virtual machine can't be serialized themselves, so
+// the file stores owned copies of them. Any value which doesn't map back onto the
+// original types makes the file corrupt.
+
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    methods: Vec<MethodRecord>,
+    instructions: Vec<InstructionRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MethodRecord {
+    name: String,
+    pattern: PatternRecord,
+    address: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+enum PatternRecord {
+    Value(ValueRecord),
+    Type(u8),
+    Any,
+}
+
+#[derive(Serialize, Deserialize)]
+enum ValueRecord {
+    Empty,
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Float32(f32),
+    Float64(f64),
+    String(String),
+    Boolean(bool),
+    Map(Vec<(String, ValueRecord)>),
+    Array(Vec<ValueRecord>),
+}
+
+#[derive(Serialize, Deserialize)]
+enum InstructionRecord {
+    Halt,
+    Load {
+        value: ValueRecord,
+        register: String,
+    },
+    Move {
+        source: String,
+        destination: String,
+    },
+    Copy {
+        source: String,
+        destination: String,
+    },
+    Push {
+        value: ValueRecord,
+        destination: String,
+    },
+    Append {
+        value: Vec<ValueRecord>,
+        destination: String,
+    },
+    Calculate {
+        method: u8,
+        operand1: String,
+        operand2: String,
+        destination: String,
+    },
+    Compare {
+        method: u8,
+        operand1: String,
+        operand2: String,
+        destination: String,
+    },
+    Bitwise {
+        method: BitwiseRecord,
+    },
+    Jump {
+        destination: u32,
+    },
+    JumpC {
+        destination: u32,
+        conditional_address: String,
+    },
+    Interrupt {
+        address: String,
+        kind: u8,
+    },
+    Call {
+        address: u64,
+    },
+    Return,
+    StoreLocal {
+        name: String,
+        register: String,
+    },
+    LoadLocal {
+        name: String,
+        register: String,
+    },
+    Dispatch {
+        method_name: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum BitwiseRecord {
+    And { a: String, b: String, out: String },
+    Or { a: String, b: String, out: String },
+    Xor { a: String, b: String, out: String },
+    Not { a: String, out: String },
+    Lsh { a: String, out: String, amount: u32 },
+    Rsh { a: String, out: String, amount: u32 },
+}
+
+/// The number of variants of `CalculationMethod` and `ComparisonMethod`, which are
+/// stored as their index.
+const CALCULATION_METHODS: u8 = 7;
+const COMPARISON_METHODS: u8 = 6;
+
+const REGISTER_TYPES: &[RegisterType] = &[
+    RegisterType::Empty,
+    RegisterType::Int8,
+    RegisterType::Int16,
+    RegisterType::Int32,
+    RegisterType::Int64,
+    RegisterType::UInt8,
+    RegisterType::UInt16,
+    RegisterType::UInt32,
+    RegisterType::UInt64,
+    RegisterType::Float32,
+    RegisterType::Float64,
+    RegisterType::String,
+    RegisterType::Boolean,
+    RegisterType::Map,
+    RegisterType::Array,
+];
+
+/// The kinds of interrupts and the numbers they are stored as.
+fn interrupt_code(kind: InterruptKind) -> u8 {
+    match kind {
+        InterruptKind::Print => 0,
+        InterruptKind::Read => 1,
+    }
+}
+
+fn interrupt_kind(code: u8) -> Result<InterruptKind, BytecodeFileError> {
+    match code {
+        0 => Ok(InterruptKind::Print),
+        1 => Ok(InterruptKind::Read),
+        code => Err(corrupt(format!("unknown interrupt {}", code))),
+    }
+}
+
+fn corrupt(what: impl fmt::Display) -> BytecodeFileError {
+    BytecodeFileError::Corrupt(what.to_string())
+}
+
+impl From<&MethodEntry> for MethodRecord {
+    fn from(method: &MethodEntry) -> Self {
+        Self {
+            name: method.name.clone(),
+            pattern: match &method.pattern {
+                DispatchPattern::Value(value) => PatternRecord::Value(value.into()),
+                DispatchPattern::Type(kind) => PatternRecord::Type(*kind as u8),
+                DispatchPattern::Any => PatternRecord::Any,
+            },
+            address: method.address as u64,
+        }
+    }
+}
+
+impl TryFrom<MethodRecord> for MethodEntry {
+    type Error = BytecodeFileError;
+
+    fn try_from(method: MethodRecord) -> Result<Self, Self::Error> {
+        let pattern = match method.pattern {
+            PatternRecord::Value(value) => DispatchPattern::Value(value.into()),
+            PatternRecord::Type(kind) => DispatchPattern::Type(
+                *REGISTER_TYPES
+                    .iter()
+                    .find(|candidate| **candidate as u8 == kind)
+                    .ok_or_else(|| corrupt(format!("unknown value type {}", kind)))?,
+            ),
+            PatternRecord::Any => DispatchPattern::Any,
+        };
+
+        Ok(Self {
+            name: method.name,
+            pattern,
+            address: usize::try_from(method.address)
+                .map_err(|_| corrupt(format!("address {} is out of range", method.address)))?,
+        })
+    }
+}
+
+impl From<&RegisterValue> for ValueRecord {
+    fn from(value: &RegisterValue) -> Self {
+        match value {
+            RegisterValue::Empty => Self::Empty,
+            RegisterValue::Int8(n) => Self::Int8(*n),
+            RegisterValue::Int16(n) => Self::Int16(*n),
+            RegisterValue::Int32(n) => Self::Int32(*n),
+            RegisterValue::Int64(n) => Self::Int64(*n),
+            RegisterValue::UInt8(n) => Self::UInt8(*n),
+            RegisterValue::UInt16(n) => Self::UInt16(*n),
+            RegisterValue::UInt32(n) => Self::UInt32(*n),
+            RegisterValue::UInt64(n) => Self::UInt64(*n),
+            RegisterValue::Float32(n) => Self::Float32(*n),
+            RegisterValue::Float64(n) => Self::Float64(*n),
+            RegisterValue::String(text) => Self::String(text.clone()),
+            RegisterValue::Boolean(b) => Self::Boolean(*b),
+            RegisterValue::Map(entries) => {
+                let mut entries: Vec<(String, ValueRecord)> = entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.into()))
+                    .collect();
+                // Sort the entries so the same program always produces the same file.
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Self::Map(entries)
+            }
+            RegisterValue::Array(items) => Self::Array(items.iter().map(Self::from).collect()),
+        }
+    }
+}
+
+impl From<ValueRecord> for RegisterValue {
+    fn from(value: ValueRecord) -> Self {
+        match value {
+            ValueRecord::Empty => Self::Empty,
+            ValueRecord::Int8(n) => Self::Int8(n),
+            ValueRecord::Int16(n) => Self::Int16(n),
+            ValueRecord::Int32(n) => Self::Int32(n),
+            ValueRecord::Int64(n) => Self::Int64(n),
+            ValueRecord::UInt8(n) => Self::UInt8(n),
+            ValueRecord::UInt16(n) => Self::UInt16(n),
+            ValueRecord::UInt32(n) => Self::UInt32(n),
+            ValueRecord::UInt64(n) => Self::UInt64(n),
+            ValueRecord::Float32(n) => Self::Float32(n),
+            ValueRecord::Float64(n) => Self::Float64(n),
+            ValueRecord::String(text) => Self::String(text),
+            ValueRecord::Boolean(b) => Self::Boolean(b),
+            ValueRecord::Map(entries) => Self::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+            ValueRecord::Array(items) => Self::Array(items.into_iter().map(Self::from).collect()),
+        }
+    }
+}
+
+impl TryFrom<&Instruction> for InstructionRecord {
+    type Error = BytecodeFileError;
+
+    fn try_from(instruction: &Instruction) -> Result<Self, Self::Error> {
+        Ok(match instruction.clone() {
+            Instruction::Halt => Self::Halt,
+            Instruction::Load { value, register } => Self::Load {
+                value: (&value).into(),
+                register,
+            },
+            Instruction::Move {
+                source,
+                destination,
+            } => Self::Move {
+                source,
+                destination,
+            },
+            Instruction::Copy {
+                source,
+                destination,
+            } => Self::Copy {
+                source,
+                destination,
+            },
+            Instruction::Push { value, destination } => Self::Push {
+                value: (&value).into(),
+                destination,
+            },
+            Instruction::Append { value, destination } => Self::Append {
+                value: value.iter().map(ValueRecord::from).collect(),
+                destination,
+            },
+            Instruction::Calculate {
+                method,
+                operand1,
+                operand2,
+                destination,
+            } => Self::Calculate {
+                method: method.into(),
+                operand1,
+                operand2,
+                destination,
+            },
+            Instruction::Compare {
+                method,
+                operand1,
+                operand2,
+                destination,
+            } => Self::Compare {
+                method: method.into(),
+                operand1,
+                operand2,
+                destination,
+            },
+            Instruction::Bitwise { method } => Self::Bitwise {
+                method: match method {
+                    BitwiseMethod::AND { a, b, out } => BitwiseRecord::And { a, b, out },
+                    BitwiseMethod::OR { a, b, out } => BitwiseRecord::Or { a, b, out },
+                    BitwiseMethod::XOR { a, b, out } => BitwiseRecord::Xor { a, b, out },
+                    BitwiseMethod::NOT { a, out } => BitwiseRecord::Not { a, out },
+                    BitwiseMethod::LSH { a, out, amount } => BitwiseRecord::Lsh { a, out, amount },
+                    BitwiseMethod::RSH { a, out, amount } => BitwiseRecord::Rsh { a, out, amount },
+                },
+            },
+            Instruction::Jump { destination } => Self::Jump { destination },
+            Instruction::JumpC {
+                destination,
+                conditional_address,
+            } => Self::JumpC {
+                destination,
+                conditional_address,
+            },
+            Instruction::Interrupt { interrupt } => Self::Interrupt {
+                address: interrupt.address,
+                kind: interrupt_code(interrupt.kind),
+            },
+            Instruction::Call { address } => Self::Call {
+                address: address as u64,
+            },
+            Instruction::Return => Self::Return,
+            Instruction::StoreLocal { name, register } => Self::StoreLocal { name, register },
+            Instruction::LoadLocal { name, register } => Self::LoadLocal { name, register },
+            Instruction::Dispatch { method_name } => Self::Dispatch { method_name },
+            Instruction::LabelTarget { .. }
+            | Instruction::JumpToLabel { .. }
+            | Instruction::JumpCToLabel { .. } => return Err(BytecodeFileError::UnresolvedLabel),
+        })
+    }
+}
+
+impl TryFrom<InstructionRecord> for Instruction {
+    type Error = BytecodeFileError;
+
+    fn try_from(instruction: InstructionRecord) -> Result<Self, Self::Error> {
+        Ok(match instruction {
+            InstructionRecord::Halt => Self::Halt,
+            InstructionRecord::Load { value, register } => Self::Load {
+                value: value.into(),
+                register,
+            },
+            InstructionRecord::Move {
+                source,
+                destination,
+            } => Self::Move {
+                source,
+                destination,
+            },
+            InstructionRecord::Copy {
+                source,
+                destination,
+            } => Self::Copy {
+                source,
+                destination,
+            },
+            InstructionRecord::Push { value, destination } => Self::Push {
+                value: value.into(),
+                destination,
+            },
+            InstructionRecord::Append { value, destination } => Self::Append {
+                value: value.into_iter().map(RegisterValue::from).collect(),
+                destination,
+            },
+            InstructionRecord::Calculate {
+                method,
+                operand1,
+                operand2,
+                destination,
+            } if method < CALCULATION_METHODS => Self::Calculate {
+                method: CalculationMethod::from(method),
+                operand1,
+                operand2,
+                destination,
+            },
+            InstructionRecord::Compare {
+                method,
+                operand1,
+                operand2,
+                destination,
+            } if method < COMPARISON_METHODS => Self::Compare {
+                method: ComparisonMethod::from(method),
+                operand1,
+                operand2,
+                destination,
+            },
+            InstructionRecord::Calculate { method, .. }
+            | InstructionRecord::Compare { method, .. } => {
+                return Err(corrupt(format!("unknown operation {}", method)))
+            }
+            InstructionRecord::Bitwise { method } => Self::Bitwise {
+                method: match method {
+                    BitwiseRecord::And { a, b, out } => BitwiseMethod::AND { a, b, out },
+                    BitwiseRecord::Or { a, b, out } => BitwiseMethod::OR { a, b, out },
+                    BitwiseRecord::Xor { a, b, out } => BitwiseMethod::XOR { a, b, out },
+                    BitwiseRecord::Not { a, out } => BitwiseMethod::NOT { a, out },
+                    BitwiseRecord::Lsh { a, out, amount } => BitwiseMethod::LSH { a, out, amount },
+                    BitwiseRecord::Rsh { a, out, amount } => BitwiseMethod::RSH { a, out, amount },
+                },
+            },
+            InstructionRecord::Jump { destination } => Self::Jump { destination },
+            InstructionRecord::JumpC {
+                destination,
+                conditional_address,
+            } => Self::JumpC {
+                destination,
+                conditional_address,
+            },
+            InstructionRecord::Interrupt { address, kind } => Self::Interrupt {
+                interrupt: Interrupt {
+                    address,
+                    kind: interrupt_kind(kind)?,
+                },
+            },
+            InstructionRecord::Call { address } => Self::Call {
+                address: usize::try_from(address)
+                    .map_err(|_| corrupt(format!("address {} is out of range", address)))?,
+            },
+            InstructionRecord::Return => Self::Return,
+            InstructionRecord::StoreLocal { name, register } => Self::StoreLocal { name, register },
+            InstructionRecord::LoadLocal { name, register } => Self::LoadLocal { name, register },
+            InstructionRecord::Dispatch { method_name } => Self::Dispatch { method_name },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file() -> BytecodeFile {
+        BytecodeFile {
+            methods: vec![MethodEntry {
+                name: "double".to_string(),
+                pattern: DispatchPattern::Type(RegisterType::Int64),
+                address: 0,
+            }],
+            instructions: vec![
+                Instruction::Load {
+                    value: RegisterValue::Int64(21),
+                    register: "ret".to_string(),
+                },
+                Instruction::Halt,
+            ],
+            prelude: true,
+        }
+    }
+
+    fn bytes(file: &BytecodeFile) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let written = file();
+        let read = BytecodeFile::read(&bytes(&written)).unwrap();
+
+        assert_eq!(read.instructions, written.instructions);
+        assert!(read.prelude);
+        assert_eq!(read.methods.len(), 1);
+        assert_eq!(read.methods[0].name, "double");
+        assert_eq!(read.methods[0].address, 0);
+    }
+
+    #[test]
+    fn reads_every_kind_of_interrupt() {
+        for kind in [InterruptKind::Print, InterruptKind::Read] {
+            let mut written = file();
+            written.instructions.insert(
+                0,
+                Instruction::Interrupt {
+                    interrupt: Interrupt {
+                        address: "r0".to_string(),
+                        kind,
+                    },
+                },
+            );
+            let read = BytecodeFile::read(&bytes(&written)).unwrap();
+
+            assert_eq!(read.instructions, written.instructions);
+        }
+    }
+
+    #[test]
+    fn rejects_files_without_the_header() {
+        assert!(!BytecodeFile::is_bytecode(b"def main() 1"));
+        assert!(matches!(
+            BytecodeFile::read(b"def main() 1"),
+            Err(BytecodeFileError::NotBytecode)
+        ));
+        assert!(matches!(
+            BytecodeFile::read(b"MAGB\x01"),
+            Err(BytecodeFileError::NotBytecode)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let mut bytes = bytes(&file());
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        assert!(matches!(
+            BytecodeFile::read(&bytes),
+            Err(BytecodeFileError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_unresolved_labels() {
+        let mut file = file();
+        file.instructions
+            .insert(0, Instruction::LabelTarget { id: 0 });
+
+        assert!(matches!(
+            file.write(Vec::new()),
+            Err(BytecodeFileError::UnresolvedLabel)
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_operations() {
+        let record = InstructionRecord::Compare {
+            method: COMPARISON_METHODS,
+            operand1: "a".to_string(),
+            operand2: "b".to_string(),
+            destination: "c".to_string(),
+        };
+
+        assert!(matches!(
+            Instruction::try_from(record),
+            Err(BytecodeFileError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_contents() {
+        let bytes = bytes(&file());
+
+        assert!(matches!(
+            BytecodeFile::read(&bytes[..bytes.len() - 1]),
+            Err(BytecodeFileError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = bytes(&file());
+        bytes.push(0);
+
+        assert!(matches!(
+            BytecodeFile::read(&bytes),
+            Err(BytecodeFileError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_addresses_outside_the_program() {
+        let mut outside = file();
+        outside.methods[0].address = link::size_of(&outside.instructions) + 1;
+
+        let mut inside = file();
+        inside
+            .instructions
+            .insert(0, Instruction::Jump { destination: 1 });
+
+        let mut ending = file();
+        let jump = Instruction::Jump { destination: 0 };
+        let end = link::size_of(&ending.instructions) + link::encoded_size(&jump);
+        ending.instructions.push(Instruction::Jump {
+            destination: end as u32,
+        });
+
+        for (file, valid) in [(outside, false), (inside, false), (ending, true)] {
+            let result = BytecodeFile::read(&bytes(&file));
+
+            assert_eq!(result.is_ok(), valid);
+        }
+    }
+}
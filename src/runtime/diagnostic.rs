@@ -0,0 +1,452 @@
+use colored::*;
+use magc::types::ParserError;
+use strontium::machine::bytecode::BytecodeError;
+use strontium::types::StrontiumError;
+
+use super::error::{describe_machine_error, describe_parser_error, words};
+use super::source::line_column;
+use super::{Frame, Location, RuntimeError};
+
+/// The number of innermost and outermost frames shown for long backtraces, which
+/// deeply recursive multimethods can easily produce.
+const BACKTRACE_HEAD: usize = 10;
+const BACKTRACE_TAIL: usize = 5;
+
+/// A range of characters in a source string, used to point diagnostics at code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// The index of the first character in the span.
+    pub start: usize,
+    /// The index one past the last character in the span.
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self {
+            start,
+            end: end.max(start),
+        }
+    }
+}
+
+/// A message attached to a specific location in the source code.
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub location: Location,
+    pub message: String,
+}
+
+/// A user-facing description of a problem, which can be rendered together with the
+/// source code it refers to.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+    pub labels: Vec<Label>,
+    /// Additional hints shown below the annotated source as `help:` notes.
+    pub help: Vec<String>,
+    /// The call chain which led to the error, innermost frame first.
+    pub backtrace: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            labels: vec![],
+            help: vec![],
+            backtrace: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, location: Location, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            location,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    pub fn with_backtrace(mut self, frames: impl IntoIterator<Item = String>) -> Self {
+        self.backtrace.extend(frames);
+        self
+    }
+
+    /// Render the diagnostic with the annotated source lines, showing `context` lines
+    /// before each labelled line.
+    pub fn render(&self, context: usize) -> String {
+        let mut output = format!("{} {}\n", "error:".bright_red().bold(), self.message.bold());
+
+        let gutter = self
+            .labels
+            .iter()
+            .map(|label| line_column(&label.location.source.text, label.location.span.end).0 + 1)
+            .max()
+            .unwrap_or(0)
+            .to_string()
+            .len();
+        let empty_gutter = format!("{} |", " ".repeat(gutter));
+
+        let mut current_source: Option<&str> = None;
+        let mut printed_until = 0;
+
+        for label in &self.labels {
+            let source = &label.location.source;
+            let span = label.location.span;
+            let (line, column) = line_column(&source.text, span.start);
+            let (end_line, _) = line_column(&source.text, span.end);
+            let lines: Vec<&str> = source.text.lines().collect();
+
+            if current_source != Some(source.name.as_str()) {
+                if current_source.is_some() {
+                    output.push_str(&format!("{}\n", empty_gutter.blue().bold()));
+                }
+
+                output.push_str(&format!(
+                    "{}{} {}:{}:{}\n",
+                    " ".repeat(gutter),
+                    "-->".blue().bold(),
+                    source.name,
+                    line + 1,
+                    column + 1
+                ));
+                output.push_str(&format!("{}\n", empty_gutter.blue().bold()));
+
+                current_source = Some(source.name.as_str());
+                printed_until = 0;
+            }
+
+            let first = line.saturating_sub(context).max(printed_until).min(line);
+
+            for index in first..=end_line {
+                let text = lines.get(index).copied().unwrap_or("");
+                output.push_str(&format!(
+                    "{} {}\n",
+                    format!("{:>width$} |", index + 1, width = gutter)
+                        .blue()
+                        .bold(),
+                    text
+                ));
+
+                if index >= line {
+                    let (start, end) = underline_range(&source.text, span, index, text);
+                    let marker = if index == end_line {
+                        format!("{} {}", "^".repeat(end - start), label.message)
+                    } else {
+                        "^".repeat(end - start)
+                    };
+
+                    output.push_str(&format!(
+                        "{} {}{}\n",
+                        empty_gutter.blue().bold(),
+                        " ".repeat(start),
+                        marker.bright_red().bold()
+                    ));
+                }
+            }
+
+            printed_until = end_line + 1;
+        }
+
+        if !self.labels.is_empty() {
+            output.push_str(&format!("{}\n", empty_gutter.blue().bold()));
+        }
+
+        for help in &self.help {
+            output.push_str(&format!(
+                "{} {} {}\n",
+                format!("{} =", " ".repeat(gutter)).blue().bold(),
+                "help:".bold(),
+                help
+            ));
+        }
+
+        if !self.backtrace.is_empty() {
+            output.push_str(&format!(
+                "{}\n",
+                "backtrace (most recent call first):".bold()
+            ));
+
+            for (index, frame) in self.backtrace.iter().enumerate() {
+                if index == BACKTRACE_HEAD && self.backtrace.len() > BACKTRACE_HEAD + BACKTRACE_TAIL
+                {
+                    let omitted = self.backtrace.len() - BACKTRACE_HEAD - BACKTRACE_TAIL;
+                    output.push_str(&format!(
+                        "{}\n",
+                        format!("  ... {} frames omitted ...", omitted).dimmed()
+                    ));
+                }
+
+                if index < BACKTRACE_HEAD
+                    || index >= self.backtrace.len().saturating_sub(BACKTRACE_TAIL)
+                {
+                    output.push_str(&format!(
+                        "{} {}\n",
+                        format!("{:>4}:", index).blue().bold(),
+                        frame
+                    ));
+                }
+            }
+        }
+
+        output
+    }
+}
+
+impl RuntimeError {
+    /// Describe the error as a diagnostic pointing at the code which caused it.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Self::Parser { error, location } => {
+                let diagnostic = match error.as_ref() {
+                    ParserError::UnexpectedEOF => Diagnostic::new("unexpected end of input")
+                        .with_help("check for an unclosed bracket, string or `do` block"),
+                    ParserError::UnexpectedToken { expected, .. } => {
+                        Diagnostic::new(describe_parser_error(error))
+                            .with_help(format!("expected {} here", words(expected)))
+                    }
+                    ParserError::MissingPrefixParselet(_) => {
+                        Diagnostic::new(describe_parser_error(error))
+                            .with_help("this token cannot start an expression")
+                    }
+                    error => Diagnostic::new(describe_parser_error(error)),
+                };
+
+                match location {
+                    Some(location) => diagnostic.with_label(location.clone(), "syntax error here"),
+                    None => diagnostic,
+                }
+            }
+            Self::Compiler { error, location } => {
+                let diagnostic = Diagnostic::new(format!("{}", error));
+
+                match location {
+                    Some(location) => {
+                        diagnostic.with_label(location.clone(), "while compiling this expression")
+                    }
+                    None => diagnostic,
+                }
+            }
+            Self::Machine {
+                error,
+                location,
+                backtrace,
+            } => {
+                let diagnostic = match error.as_ref() {
+                    StrontiumError::BytecodeError(BytecodeError::UnexpectedEof(_)) => {
+                        Diagnostic::new(describe_machine_error(error))
+                            .with_help("the bytecode may be truncated or corrupted")
+                    }
+                    error => Diagnostic::new(describe_machine_error(error)),
+                };
+
+                // A backtrace only adds information once a multimethod call is involved.
+                let diagnostic = if backtrace.iter().any(|frame| frame.method.is_some()) {
+                    diagnostic.with_backtrace(backtrace.iter().map(Frame::to_string))
+                } else {
+                    diagnostic
+                };
+
+                match location {
+                    Some(location) => {
+                        diagnostic.with_label(location.clone(), "while evaluating this expression")
+                    }
+                    None => diagnostic,
+                }
+            }
+            Self::Native {
+                message,
+                location,
+                backtrace,
+                ..
+            } => {
+                let diagnostic = Diagnostic::new(message.clone())
+                    .with_backtrace(backtrace.iter().map(Frame::to_string));
+
+                match location {
+                    Some(location) => {
+                        diagnostic.with_label(location.clone(), "while evaluating this expression")
+                    }
+                    None => diagnostic,
+                }
+            }
+            Self::ModuleNotFound {
+                name,
+                searched,
+                location,
+            } => searched
+                .iter()
+                .fold(
+                    Diagnostic::new(format!("module `{}` not found", name))
+                        .with_label(location.clone(), "imported here"),
+                    |diagnostic, path| {
+                        diagnostic.with_help(format!("looked for '{}'", path.display()))
+                    },
+                )
+                .with_help("add the module's directory to `MAG_PATH` to import it from elsewhere"),
+            Self::ModuleUnreadable { location, .. } => {
+                Diagnostic::new(self.to_string()).with_label(location.clone(), "imported here")
+            }
+            Self::ModuleNameClash {
+                name,
+                existing,
+                location,
+                ..
+            } => Diagnostic::new(self.to_string())
+                .with_label(location.clone(), "imported here")
+                .with_help(format!(
+                    "`{}` was already loaded from '{}'",
+                    name,
+                    existing.display()
+                ))
+                .with_help("rename one of the modules so their methods get different names"),
+            Self::ImportCycle { cycle, location } => Diagnostic::new(format!(
+                "module `{}` imports itself",
+                cycle.last().map(String::as_str).unwrap_or_default()
+            ))
+            .with_label(location.clone(), "cyclic import here")
+            .with_help(format!("the import chain is {}", cycle.join(" -> ")))
+            .with_help("move the definitions both modules need into a separate module"),
+            Self::InvalidSignature(signature) => Diagnostic::new(format!(
+                "invalid native method signature `{}`",
+                signature
+            ))
+            .with_help(
+                "signatures look like method definitions without a body, such as `print(s String)`",
+            ),
+            Self::UnresolvedLabel(_) => Diagnostic::new(self.to_string()).with_help(
+                "this is a bug in the compiler, since every label it jumps to should exist",
+            ),
+            Self::PreludeMismatch { built_with_prelude } => Diagnostic::new(self.to_string())
+                .with_help(if *built_with_prelude {
+                    "run it with `--prelude` to load the prelude"
+                } else {
+                    "run it with `--no-prelude`"
+                }),
+            Self::Interrupted => Diagnostic::new("interrupted"),
+            Self::TimedOut(limit) => Diagnostic::new(format!("timed out after {:?}", limit))
+                .with_help("the run took longer than the runtime's time limit allows"),
+        }
+    }
+}
+
+/// Find the columns of the given line which are covered by a span, always marking
+/// at least one column so that empty spans (like the end of the input) stay visible.
+fn underline_range(source: &str, span: Span, line: usize, text: &str) -> (usize, usize) {
+    let (start_line, start_column) = line_column(source, span.start);
+    let (end_line, end_column) = line_column(source, span.end);
+    let length = text.chars().count();
+
+    let start = if line == start_line { start_column } else { 0 };
+    let end = if line == end_line { end_column } else { length };
+
+    (start, end.max(start + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::super::Source;
+    use super::*;
+
+    const SOURCE: &str = "def double(n Int)\n    n * 2\nend\ndouble(\"two\")";
+
+    fn location(start: usize, end: usize) -> Location {
+        Location::new(
+            Arc::new(Source::new("main.mag", SOURCE)),
+            Span::new(start, end),
+        )
+    }
+
+    /// Render a diagnostic without its colors. Whether colors are used is global state
+    /// shared with other tests, so they are stripped instead of turned off.
+    fn render(diagnostic: &Diagnostic, context: usize) -> String {
+        let mut plain = String::new();
+        let mut chars = diagnostic.render(context).chars();
+
+        while let Some(ch) = chars.next() {
+            if ch == '\x1b' {
+                // Skip the escape sequence up to its final letter, like the `m` of colors.
+                for ch in chars.by_ref() {
+                    if ch.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            } else {
+                plain.push(ch);
+            }
+        }
+
+        plain
+    }
+
+    #[test]
+    fn underlines_the_span_on_a_single_line() {
+        // `"two"` on the last line.
+        assert_eq!(
+            underline_range(SOURCE, Span::new(39, 44), 3, "double(\"two\")"),
+            (7, 12)
+        );
+    }
+
+    #[test]
+    fn underlines_the_rest_of_each_line_a_span_covers() {
+        let span = Span::new(4, 28);
+
+        assert_eq!(
+            underline_range(SOURCE, span, 0, "def double(n Int)"),
+            (4, 17)
+        );
+        assert_eq!(underline_range(SOURCE, span, 1, "    n * 2"), (0, 9));
+    }
+
+    #[test]
+    fn underlines_at_least_one_column() {
+        let end = SOURCE.chars().count();
+        assert_eq!(
+            underline_range(SOURCE, Span::new(end, end), 3, "double(\"two\")"),
+            (13, 14)
+        );
+    }
+
+    #[test]
+    fn renders_labels_with_their_position() {
+        let diagnostic = Diagnostic::new("no method matches")
+            .with_label(location(39, 44), "this is a string")
+            .with_help("pass an integer");
+
+        assert_eq!(
+            render(&diagnostic, 0),
+            "error: no method matches\n \
+             --> main.mag:4:8\n  \
+             |\n\
+             4 | double(\"two\")\n  \
+             |        ^^^^^ this is a string\n  \
+             |\n  \
+             = help: pass an integer\n"
+        );
+    }
+
+    #[test]
+    fn renders_context_lines_before_the_label() {
+        let diagnostic = Diagnostic::new("unexpected token").with_label(location(22, 23), "here");
+        let output = render(&diagnostic, 1);
+
+        assert!(output.contains("1 | def double(n Int)\n2 |     n * 2\n  |     ^ here\n"));
+    }
+
+    #[test]
+    fn shortens_long_backtraces() {
+        let frames = (0..20).map(|index| format!("frame {}", index));
+        let output = render(&Diagnostic::new("too deep").with_backtrace(frames), 0);
+
+        assert!(output.contains("   9: frame 9\n  ... 5 frames omitted ...\n  15: frame 15\n"));
+        assert!(!output.contains("frame 10\n"));
+    }
+}
@@ -0,0 +1,234 @@
+use std::fmt::Write;
+
+use strontium::machine::instruction::{BitwiseMethod, DispatchPattern};
+use strontium::machine::register::{RegisterType, RegisterValue};
+use strontium::Instruction;
+
+use super::value::quote_string;
+use super::{link, Runtime, Value};
+
+impl Runtime {
+    /// Render compiled instructions as a numbered listing, starting at the given address.
+    ///
+    /// Each multimethod entry point is labelled with its name and pattern, and every
+    /// block of instructions is preceded by the source line it was compiled from.
+    pub fn disassemble(&self, instructions: &[Instruction], start: usize) -> String {
+        let mut listing = String::new();
+        let width = (start + link::size_of(instructions))
+            .to_string()
+            .len()
+            .max(4);
+        let mut previous_span = None;
+        let mut address = start;
+
+        for instruction in instructions {
+            if let Some(location) = self.source_map.lookup(address) {
+                let span = (location.source.name.as_str(), location.span);
+
+                if previous_span != Some(span) {
+                    let (line, _) = location.line_column();
+                    let text = location.source.text.lines().nth(line).unwrap_or("").trim();
+
+                    if previous_span.is_some() {
+                        listing.push('\n');
+                    }
+
+                    let _ = writeln!(listing, "; {}:{}  {}", location.source.name, line + 1, text);
+                    previous_span = Some(span);
+                }
+            }
+
+            for method in self.methods().filter(|method| method.address == address) {
+                let _ = writeln!(
+                    listing,
+                    "{}({}):",
+                    method.name,
+                    format_pattern(&method.pattern)
+                );
+            }
+
+            let _ = writeln!(
+                listing,
+                "  {:0width$}  {}",
+                address,
+                format_instruction(instruction),
+                width = width
+            );
+            address += link::encoded_size(instruction);
+        }
+
+        listing
+    }
+}
+
+/// Write the dispatch pattern of a multimethod implementation the way it would appear
+/// in a Mag signature: a literal like `0` or `"name"`, a type like `Int`, or `_` for a
+/// pattern which matches anything.
+pub fn format_pattern(pattern: &DispatchPattern) -> String {
+    match pattern {
+        DispatchPattern::Value(value) => format_literal(value),
+        DispatchPattern::Type(kind) => type_name(*kind).to_string(),
+        DispatchPattern::Any => "_".to_string(),
+    }
+}
+
+/// Write an instruction as its mnemonic followed by its operands, with the register
+/// an instruction writes to after an arrow.
+fn format_instruction(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::Halt => "halt".to_string(),
+        Instruction::Load { value, register } => {
+            format!("load {} -> {}", format_literal(value), register)
+        }
+        Instruction::Move {
+            source,
+            destination,
+        } => format!("move {} -> {}", source, destination),
+        Instruction::Copy {
+            source,
+            destination,
+        } => format!("copy {} -> {}", source, destination),
+        Instruction::Push { value, destination } => {
+            format!("push {} -> {}", format_literal(value), destination)
+        }
+        Instruction::Append { value, destination } => format!(
+            "append {} -> {}",
+            value
+                .iter()
+                .map(format_literal)
+                .collect::<Vec<_>>()
+                .join(", "),
+            destination
+        ),
+        Instruction::Calculate {
+            method,
+            operand1,
+            operand2,
+            destination,
+        } => format!(
+            "{} {}, {} -> {}",
+            format!("{:?}", method).to_lowercase(),
+            operand1,
+            operand2,
+            destination
+        ),
+        Instruction::Compare {
+            method,
+            operand1,
+            operand2,
+            destination,
+        } => format!(
+            "{} {}, {} -> {}",
+            format!("{:?}", method).to_lowercase(),
+            operand1,
+            operand2,
+            destination
+        ),
+        Instruction::Bitwise { method } => match method {
+            BitwiseMethod::AND { a, b, out } => format!("and {}, {} -> {}", a, b, out),
+            BitwiseMethod::OR { a, b, out } => format!("or {}, {} -> {}", a, b, out),
+            BitwiseMethod::XOR { a, b, out } => format!("xor {}, {} -> {}", a, b, out),
+            BitwiseMethod::NOT { a, out } => format!("not {} -> {}", a, out),
+            BitwiseMethod::LSH { a, out, amount } => format!("lsh {}, {} -> {}", a, amount, out),
+            BitwiseMethod::RSH { a, out, amount } => format!("rsh {}, {} -> {}", a, amount, out),
+        },
+        Instruction::Jump { destination } => format!("jump {}", destination),
+        Instruction::JumpC {
+            destination,
+            conditional_address,
+        } => format!("jumpc {} if {}", destination, conditional_address),
+        Instruction::Interrupt { interrupt } => format!(
+            "interrupt {} {}",
+            format!("{:?}", interrupt.kind).to_lowercase(),
+            interrupt.address
+        ),
+        Instruction::Call { address } => format!("call {}", address),
+        Instruction::Return => "return".to_string(),
+        Instruction::StoreLocal { name, register } => {
+            format!("store_local {} -> {}", register, name)
+        }
+        Instruction::LoadLocal { name, register } => format!("load_local {} -> {}", name, register),
+        Instruction::Dispatch { method_name } => format!("dispatch {}", method_name),
+        Instruction::LabelTarget { id } => format!("label {}", id),
+        Instruction::JumpToLabel { id } => format!("jump label {}", id),
+        Instruction::JumpCToLabel {
+            id,
+            conditional_address,
+        } => format!("jumpc label {} if {}", id, conditional_address),
+    }
+}
+
+/// Write a register value as a Mag literal, with strings quoted and escaped.
+fn format_literal(value: &RegisterValue) -> String {
+    match Value::from(value) {
+        Value::String(text) => quote_string(&text),
+        value => value.to_string(),
+    }
+}
+
+/// The name of the Mag type a register type holds, which doesn't distinguish sizes.
+fn type_name(kind: RegisterType) -> &'static str {
+    match kind {
+        RegisterType::Empty => "Empty",
+        RegisterType::Int8 | RegisterType::Int16 | RegisterType::Int32 | RegisterType::Int64 => {
+            "Int"
+        }
+        RegisterType::UInt8
+        | RegisterType::UInt16
+        | RegisterType::UInt32
+        | RegisterType::UInt64 => "UInt",
+        RegisterType::Float32 | RegisterType::Float64 => "Float",
+        RegisterType::String => "String",
+        RegisterType::Boolean => "Bool",
+        RegisterType::Map => "Map",
+        RegisterType::Array => "List",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_patterns_in_mag_syntax() {
+        let patterns = [
+            DispatchPattern::Value(RegisterValue::Int64(0)),
+            DispatchPattern::Value(RegisterValue::String("a \"b\"".to_string())),
+            DispatchPattern::Type(RegisterType::Int64),
+            DispatchPattern::Type(RegisterType::Array),
+            DispatchPattern::Any,
+        ];
+        let formatted: Vec<String> = patterns.iter().map(format_pattern).collect();
+
+        assert_eq!(formatted, ["0", "\"a \\\"b\\\"\"", "Int", "List", "_"]);
+    }
+
+    #[test]
+    fn writes_instructions_as_mnemonics() {
+        let instructions = [
+            Instruction::Load {
+                value: RegisterValue::Boolean(true),
+                register: "r0".to_string(),
+            },
+            Instruction::Dispatch {
+                method_name: "fib".to_string(),
+            },
+            Instruction::JumpC {
+                destination: 12,
+                conditional_address: "r1".to_string(),
+            },
+            Instruction::Return,
+        ];
+        let formatted: Vec<String> = instructions.iter().map(format_instruction).collect();
+
+        assert_eq!(
+            formatted,
+            [
+                "load true -> r0",
+                "dispatch fib",
+                "jumpc 12 if r1",
+                "return"
+            ]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use magc::types::{CompilerError, ParserError, TokenKind};
+use strontium::machine::bytecode::BytecodeError;
+use strontium::types::StrontiumError;
+
+use super::{Frame, Location};
+
+/// An error raised by one of the stages of the runtime pipeline.
+///
+/// The errors of the compiler and the virtual machine are boxed, which keeps results
+/// holding a `RuntimeError` small.
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The source could not be parsed into a tree of expressions.
+    Parser {
+        error: Box<ParserError>,
+        location: Option<Location>,
+    },
+    /// An expression could not be compiled into instructions.
+    Compiler {
+        error: Box<CompilerError>,
+        location: Option<Location>,
+    },
+    /// The virtual machine failed while executing the compiled instructions.
+    Machine {
+        error: Box<StrontiumError>,
+        /// The expression which was being evaluated when the error occurred.
+        location: Option<Location>,
+        /// The multimethod calls which were in progress, innermost first.
+        backtrace: Vec<Frame>,
+    },
+    /// A native method returned an error.
+    Native {
+        method: String,
+        message: String,
+        /// The expression which called the method.
+        location: Option<Location>,
+        /// The multimethod calls which were in progress, innermost first.
+        backtrace: Vec<Frame>,
+    },
+    /// An imported module couldn't be found next to the importing script or in the
+    /// search path.
+    ModuleNotFound {
+        name: String,
+        /// Every path where the module's source file was looked for.
+        searched: Vec<PathBuf>,
+        location: Location,
+    },
+    /// An imported module was found but couldn't be read.
+    ModuleUnreadable {
+        name: String,
+        path: PathBuf,
+        error: io::Error,
+        location: Location,
+    },
+    /// Two different files were imported under the same module name, so their
+    /// multimethods would share qualified names.
+    ModuleNameClash {
+        name: String,
+        /// The file the name resolved to at this import.
+        path: PathBuf,
+        /// The file which was loaded under the name before.
+        existing: PathBuf,
+        location: Location,
+    },
+    /// A module imports itself, directly or through other modules.
+    ImportCycle {
+        /// The names of the modules in the cycle, starting and ending with the same one.
+        cycle: Vec<String>,
+        location: Location,
+    },
+    /// A native method signature didn't describe a single method implementation.
+    InvalidSignature(String),
+    /// The compiler emitted a jump to a label it never placed.
+    UnresolvedLabel(String),
+    /// A bytecode file was built with the prelude and the runtime has none loaded, or
+    /// the other way around.
+    PreludeMismatch {
+        /// Whether the file was built with the prelude.
+        built_with_prelude: bool,
+    },
+    /// Execution was cancelled before it finished.
+    Interrupted,
+    /// Execution was stopped after running longer than the configured time limit.
+    TimedOut(Duration),
+}
+
+impl RuntimeError {
+    /// Returns true if the error happened before any code was executed.
+    pub fn is_compile_error(&self) -> bool {
+        matches!(
+            self,
+            Self::Parser { .. }
+                | Self::Compiler { .. }
+                | Self::ModuleNotFound { .. }
+                | Self::ModuleUnreadable { .. }
+                | Self::ModuleNameClash { .. }
+                | Self::ImportCycle { .. }
+                | Self::UnresolvedLabel(_)
+        )
+    }
+
+    /// Returns true if the source ended in the middle of an expression, so that more
+    /// input could still make it valid.
+    pub fn is_incomplete_input(&self) -> bool {
+        matches!(
+            self,
+            Self::Parser { error, .. } if matches!(**error, ParserError::UnexpectedEOF)
+        )
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parser { error, .. } => {
+                write!(f, "parse error: {}", describe_parser_error(error))
+            }
+            Self::Compiler { error, .. } => write!(f, "compile error: {}", error),
+            Self::Machine { error, .. } => {
+                write!(f, "runtime error: {}", describe_machine_error(error))
+            }
+            Self::Native {
+                method, message, ..
+            } => write!(f, "error in `{}`: {}", method, message),
+            Self::ModuleNotFound { name, .. } => write!(f, "module `{}` not found", name),
+            Self::ModuleUnreadable {
+                name, path, error, ..
+            } => write!(
+                f,
+                "failed to read module `{}` from '{}': {}",
+                name,
+                path.display(),
+                error
+            ),
+            Self::ModuleNameClash {
+                name,
+                path,
+                existing,
+                ..
+            } => write!(
+                f,
+                "module `{}` from '{}' clashes with the module of the same name from '{}'",
+                name,
+                path.display(),
+                existing.display()
+            ),
+            Self::ImportCycle { cycle, .. } => {
+                write!(f, "import cycle: {}", cycle.join(" -> "))
+            }
+            Self::InvalidSignature(signature) => {
+                write!(f, "invalid native method signature `{}`", signature)
+            }
+            Self::UnresolvedLabel(label) => {
+                write!(f, "compile error: jump to the missing label {}", label)
+            }
+            Self::PreludeMismatch {
+                built_with_prelude: true,
+            } => write!(f, "the bytecode file needs the prelude, which isn't loaded"),
+            Self::PreludeMismatch {
+                built_with_prelude: false,
+            } => write!(f, "the bytecode file was built without the prelude"),
+            Self::Interrupted => write!(f, "interrupted"),
+            Self::TimedOut(limit) => write!(f, "timed out after {:?}", limit),
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ModuleUnreadable { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Describe a parser error in words, naming tokens the way they are written.
+pub(super) fn describe_parser_error(error: &ParserError) -> String {
+    match error {
+        ParserError::UnexpectedEOF => "unexpected end of input".to_string(),
+        ParserError::UnexpectedToken { found, .. } => {
+            format!("unexpected {}", describe_token(&found.kind))
+        }
+        ParserError::MissingPrefixParselet(kind) => {
+            format!("expected an expression, found {}", describe_token(kind))
+        }
+        error => words(error),
+    }
+}
+
+/// Describe an error of the virtual machine in words.
+pub(super) fn describe_machine_error(error: &StrontiumError) -> String {
+    match error {
+        StrontiumError::BytecodeError(BytecodeError::UnexpectedEof(_)) => {
+            "the program ended in the middle of an instruction".to_string()
+        }
+        StrontiumError::EmptyCallStack => "returned from a method which wasn't called".to_string(),
+        StrontiumError::Interrupted => "execution was interrupted".to_string(),
+        error => words(error),
+    }
+}
+
+/// Name a kind of token, quoting punctuation and keywords the way they are written.
+pub(super) fn describe_token(kind: &TokenKind) -> String {
+    match kind {
+        TokenKind::LeftParen => "`(`".to_string(),
+        TokenKind::RightParen => "`)`".to_string(),
+        TokenKind::LeftBracket => "`[`".to_string(),
+        TokenKind::RightBracket => "`]`".to_string(),
+        TokenKind::Dot => "`.`".to_string(),
+        TokenKind::Plus => "`+`".to_string(),
+        TokenKind::Slash => "`/`".to_string(),
+        TokenKind::Keyword(keyword) => format!("`{}`", format!("{:?}", keyword).to_lowercase()),
+        TokenKind::Identifier => "an identifier".to_string(),
+        TokenKind::Type => "a type name".to_string(),
+        TokenKind::Literal(_) => "a literal".to_string(),
+        kind => words(kind),
+    }
+}
+
+/// Spell out the debug representation of a value, like `DivisionByZero` as
+/// `division by zero`, for errors which have no message of their own.
+pub(super) fn words(value: &impl fmt::Debug) -> String {
+    let mut text = String::new();
+    let mut previous = None;
+
+    for ch in format!("{:?}", value).chars() {
+        if ch.is_uppercase() && previous.is_some_and(char::is_alphanumeric) {
+            text.push(' ');
+        }
+        text.extend(ch.to_lowercase());
+        previous = Some(ch);
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Source, Span};
+    use std::sync::Arc;
+
+    #[test]
+    fn spells_out_debug_names() {
+        #[derive(Debug)]
+        enum Sample {
+            DivisionByZero,
+            RegisterNotFound(&'static str),
+        }
+
+        assert_eq!(words(&Sample::DivisionByZero), "division by zero");
+        assert_eq!(
+            words(&Sample::RegisterNotFound("r1")),
+            "register not found(\"r1\")"
+        );
+    }
+
+    #[test]
+    fn describes_errors_in_words() {
+        let error = RuntimeError::Parser {
+            error: Box::new(ParserError::UnexpectedEOF),
+            location: None,
+        };
+        assert_eq!(error.to_string(), "parse error: unexpected end of input");
+
+        let error = RuntimeError::Machine {
+            error: Box::new(StrontiumError::EmptyCallStack),
+            location: None,
+            backtrace: vec![],
+        };
+        assert_eq!(
+            error.to_string(),
+            "runtime error: returned from a method which wasn't called"
+        );
+    }
+
+    #[test]
+    fn exposes_the_io_error_of_unreadable_modules() {
+        let error = RuntimeError::ModuleUnreadable {
+            name: "util".to_string(),
+            path: PathBuf::from("util.mag"),
+            error: io::Error::new(io::ErrorKind::PermissionDenied, "denied"),
+            location: Location::new(
+                Arc::new(Source::new("main.mag", "import util")),
+                Span::new(0, 11),
+            ),
+        };
+
+        let source = error
+            .source()
+            .and_then(|source| source.downcast_ref::<io::Error>());
+        assert_eq!(
+            source.map(io::Error::kind),
+            Some(io::ErrorKind::PermissionDenied)
+        );
+        assert!(RuntimeError::Interrupted.source().is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use strontium::Instruction;
+
+use super::RuntimeError;
+
+/// The number of bytes the given instruction takes up in the machine's instruction
+/// stream. Labels take up no space, and jumps to labels as much as the jumps they are
+/// resolved into.
+pub fn encoded_size(instruction: &Instruction) -> usize {
+    match instruction {
+        Instruction::LabelTarget { .. } => 0,
+        Instruction::JumpToLabel { .. } => encoded_size(&Instruction::Jump { destination: 0 }),
+        Instruction::JumpCToLabel {
+            conditional_address,
+            ..
+        } => encoded_size(&Instruction::JumpC {
+            destination: 0,
+            conditional_address: conditional_address.clone(),
+        }),
+        instruction => {
+            let bytes: Vec<u8> = instruction.clone().into();
+            bytes.len()
+        }
+    }
+}
+
+/// The number of bytes a sequence of instructions takes up.
+pub fn size_of(instructions: &[Instruction]) -> usize {
+    instructions.iter().map(encoded_size).sum()
+}
+
+/// Replace the label pseudo-instructions emitted by the compiler with jumps to absolute
+/// addresses, given the address the first instruction will be placed at.
+///
+/// A jump to a label which isn't among the instructions is an error, since the jump would
+/// have nowhere to go.
+pub fn resolve_labels(
+    instructions: Vec<Instruction>,
+    start: usize,
+) -> Result<Vec<Instruction>, RuntimeError> {
+    let mut labels = HashMap::new();
+    let mut address = start;
+
+    for instruction in &instructions {
+        if let Instruction::LabelTarget { id } = instruction {
+            labels.insert(*id, address);
+        }
+
+        address += encoded_size(instruction);
+    }
+
+    let address_of = |id| {
+        labels
+            .get(&id)
+            .map(|&address| address as u32)
+            .ok_or_else(|| RuntimeError::UnresolvedLabel(id.to_string()))
+    };
+
+    instructions
+        .into_iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::LabelTarget { .. } => None,
+            Instruction::JumpToLabel { id } => {
+                Some(address_of(id).map(|destination| Instruction::Jump { destination }))
+            }
+            Instruction::JumpCToLabel {
+                id,
+                conditional_address,
+            } => Some(address_of(id).map(|destination| Instruction::JumpC {
+                destination,
+                conditional_address,
+            })),
+            instruction => Some(Ok(instruction)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_jumps_to_placed_labels() {
+        let instructions = vec![
+            Instruction::JumpToLabel { id: 0 },
+            Instruction::LabelTarget { id: 0 },
+            Instruction::Halt,
+        ];
+        let start = 10;
+        let jump = Instruction::Jump { destination: 0 };
+        let resolved = resolve_labels(instructions, start).unwrap();
+
+        assert_eq!(
+            resolved,
+            [
+                Instruction::Jump {
+                    destination: (start + encoded_size(&jump)) as u32
+                },
+                Instruction::Halt
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_jumps_to_missing_labels() {
+        let instructions = vec![Instruction::JumpToLabel { id: 7 }, Instruction::Halt];
+
+        assert!(matches!(
+            resolve_labels(instructions, 0),
+            Err(RuntimeError::UnresolvedLabel(label)) if label == "7"
+        ));
+    }
+}
@@ -1,97 +1,1027 @@
-use colored::*;
+mod backtrace;
+mod bytecode_file;
+mod diagnostic;
+mod disasm;
+mod error;
+mod link;
+mod module;
+mod native;
+mod prelude;
+mod source;
+mod value;
+
+pub use self::backtrace::{Frame, MethodFrame};
+pub use self::bytecode_file::{BytecodeFile, BytecodeFileError, MethodEntry};
+pub use self::diagnostic::{Diagnostic, Label, Span};
+pub use self::disasm::format_pattern;
+pub use self::error::RuntimeError;
+pub use self::module::{Import, Module};
+pub use self::native::{NativeFunction, NativeMethod};
+pub use self::source::{Location, Source, SourceMap};
+pub use self::value::{quote_string, ConversionError, Value};
+pub use strontium::machine::CancellationToken;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
 use magc::compiler::Compiler;
 use magc::lexer::Lexer;
 use magc::parser::Parser;
-use magc::types::{Expression, ParserError, Token};
+use magc::types::{Expression, ExpressionKind, ParserError, Token};
+use strontium::machine::instruction::{Interrupt, InterruptKind};
+use strontium::machine::register::RegisterValue;
+use strontium::types::StrontiumError;
 use strontium::Strontium;
 
+use self::source::{char_offsets, char_span};
+
+#[derive(Clone, Debug)]
 pub struct RuntimeConfig {
+    /// Report compiled instructions and method registrations to the debug handler, and
+    /// enable the virtual machine's own debug output.
     pub debug: bool,
+    /// Load the standard library prelude when the runtime is created.
+    pub prelude: bool,
+    /// Directories searched for imported modules after the directory of the importing
+    /// script, taken from `MAG_PATH` by default.
+    pub search_path: Vec<PathBuf>,
+    /// The longest a single run may execute before it is stopped with
+    /// [`RuntimeError::TimedOut`], or `None` to let it run until it finishes.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            prelude: true,
+            search_path: module::search_path_from_env(),
+            timeout: None,
+        }
+    }
+}
+
+/// Information reported to the debug handler while the runtime is in debug mode.
+pub enum DebugEvent<'a> {
+    /// Source or bytecode was compiled or loaded, with a disassembly of its instructions.
+    Compiled { listing: &'a str },
+    /// A multimethod was registered in the VM's dispatch table.
+    MethodRegistered { method: &'a MethodEntry },
+}
+
+type DebugHandler = Box<dyn FnMut(DebugEvent)>;
+
+/// Shared with the output methods of the prelude, which are plain native functions.
+type OutputHandler = Rc<RefCell<Option<Box<dyn FnMut(&str)>>>>;
+
+/// The result of passing a piece of source code through the whole pipeline.
+pub enum RunOutcome {
+    /// Execution finished normally, holding whatever was left in the `ret` register.
+    Value(Value),
+    /// One of the pipeline stages failed before execution could finish.
+    Error(RuntimeError),
+    /// Execution was cancelled through the cancellation token before it finished.
+    Interrupted,
 }
 
 /// A runtime instance, which contains all the data structures and methods needed to
 /// compile and run a program while keeping track of its state and reporting errors.
+///
+/// The runtime never prints anything by itself; results and errors are returned to the
+/// caller, output of scripts is passed to the handler set with [`Runtime::on_output`]
+/// and debug output to the one set with [`Runtime::on_debug`].
 pub struct Runtime {
     pub config: RuntimeConfig,
     /// Converts a source string into a linear sequence of tokens.
-    pub lexer: Lexer,
+    lexer: Lexer,
     /// Assembles a sequence of tokens into a tree of expressions.
-    pub parser: Parser,
+    parser: Parser,
     /// Compiles the AST into a sequence of instructions.
-    pub compiler: Compiler,
-    pub machine: Strontium,
+    compiler: Compiler,
+    machine: Strontium,
+    /// Every instruction pushed to the machine so far, starting at address zero.
+    program: Vec<strontium::Instruction>,
+    /// The address just past the last instruction pushed to the machine.
+    end_address: usize,
+    /// Maps instruction addresses back to the expressions they were compiled from.
+    source_map: SourceMap,
+    /// The address the next compiled instruction will be placed at. Addresses are byte
+    /// offsets into the machine's instruction stream.
+    next_address: usize,
+    /// The multimethods compiled so far or loaded from a bytecode file.
+    defined_methods: Vec<MethodEntry>,
+    /// Multimethods implemented by the host application in Rust.
+    natives: Vec<NativeMethod>,
+    /// The entry points of the native methods, each holding a `HALT` instruction which
+    /// hands control back to the runtime, mapped to the index of the native method.
+    native_stubs: HashMap<usize, usize>,
+    /// The addresses of the instructions which print a register, mapped to the register
+    /// and the address of the next instruction. The runtime carries them out itself so
+    /// the output reaches the output handler instead of the process's stdout.
+    print_points: HashMap<usize, (String, usize)>,
+    /// The names and addresses of the multimethods in the VM's dispatch table, so each
+    /// is only registered once.
+    registered_methods: HashSet<(String, usize)>,
+    /// Modules loaded through `import`, each of which is only compiled once.
+    modules: Vec<Module>,
+    /// Other names loaded modules were imported under, like `lib.util` for a module
+    /// first imported as `util` from within `lib`.
+    module_aliases: Vec<Module>,
+    /// The scripts and modules currently being compiled, used to detect import cycles.
+    import_stack: Vec<Module>,
+    debug_handler: Option<DebugHandler>,
+    output_handler: OutputHandler,
 }
 
 impl Runtime {
-    pub fn new(config: RuntimeConfig) -> Self {
-        Self {
+    /// Create a new runtime, loading the standard library prelude unless it is
+    /// disabled in the configuration.
+    pub fn new(config: RuntimeConfig) -> Result<Self, RuntimeError> {
+        let mut runtime = Self {
             lexer: Lexer::new(),
             parser: Parser::new(),
             compiler: Compiler::new(),
             machine: Strontium::new(config.debug),
+            program: vec![],
+            end_address: 0,
+            source_map: SourceMap::new(),
+            next_address: 0,
+            defined_methods: vec![],
+            natives: vec![],
+            native_stubs: HashMap::new(),
+            print_points: HashMap::new(),
+            registered_methods: HashSet::new(),
+            modules: vec![],
+            module_aliases: vec![],
+            import_stack: vec![],
+            debug_handler: None,
+            output_handler: Rc::new(RefCell::new(None)),
             config,
+        };
+
+        if runtime.config.prelude {
+            runtime.load_prelude()?;
+        }
+
+        Ok(runtime)
+    }
+
+    /// Evaluate a piece of Mag source code and return its result.
+    ///
+    /// Definitions made by the source stay available to later calls.
+    pub fn eval(&mut self, source: &str) -> Result<Value, RuntimeError> {
+        match self.run(Source::new("<eval>", source), &CancellationToken::new()) {
+            RunOutcome::Value(value) => Ok(value),
+            RunOutcome::Error(e) => Err(e),
+            RunOutcome::Interrupted => Err(RuntimeError::Interrupted),
+        }
+    }
+
+    /// Set the handler which receives debug output while `config.debug` is enabled.
+    pub fn on_debug(&mut self, handler: impl FnMut(DebugEvent) + 'static) {
+        self.debug_handler = Some(Box::new(handler));
+    }
+
+    /// Turn debug output on or off while the runtime is in use, both the runtime's own
+    /// and the machine's trace of every instruction it executes. Unlike everything else,
+    /// the trace is printed to stdout by the machine itself.
+    pub fn set_debug(&mut self, debug: bool) {
+        self.config.debug = debug;
+        self.machine.debug = debug;
+    }
+
+    fn debug(&mut self, event: DebugEvent) {
+        if let Some(handler) = &mut self.debug_handler {
+            handler(event);
         }
     }
 
+    /// Set the handler which receives the text printed by scripts, like the output of
+    /// `print` and `println`. Without a handler, the output is discarded.
+    pub fn on_output(&mut self, handler: impl FnMut(&str) + 'static) {
+        *self.output_handler.borrow_mut() = Some(Box::new(handler));
+    }
+
     pub fn lex(&mut self, source: String) -> Vec<Token> {
-        self.lexer.add_text(source);
+        // Start from a fresh lexer so token positions are relative to this source.
+        self.lexer = new_lexer(source);
         self.lexer.parse()
     }
 
-    pub fn parse(&mut self, source: String) -> Result<Vec<Expression>, ParserError> {
-        self.lexer.add_text(source.clone());
-        let tokens = self.lexer.parse();
+    /// Split source into tokens like [`Runtime::lex`] does, but with their positions
+    /// counted in characters rather than graphemes, for tools like syntax highlighting
+    /// which work on the text itself.
+    pub fn tokenize(text: &str) -> Vec<Token> {
+        let mut lexer = new_lexer(text.to_string());
+        let offsets = char_offsets(&lexer);
+
+        lexer
+            .parse()
+            .into_iter()
+            .map(|token| Token {
+                start_pos: offsets[token.start_pos],
+                end_pos: offsets[token.end_pos],
+                ..token
+            })
+            .collect()
+    }
+
+    pub fn parse(&mut self, source: &Arc<Source>) -> Result<Vec<Expression>, RuntimeError> {
+        let tokens = self.lex(source.text.clone());
+        let (tokens, imports) = module::take_imports(tokens, &self.lexer);
+        let modules: Vec<String> = self
+            .modules
+            .iter()
+            .chain(&self.module_aliases)
+            .map(|module| module.name.clone())
+            .chain(imports.into_iter().map(|import| import.name))
+            .collect();
+        let tokens = module::qualify_references(tokens, &self.lexer, &modules);
+
+        let offsets = char_offsets(&self.lexer);
+
+        self.parser = Parser::new();
+        self.parser.add_tokens(source.text.clone(), tokens.clone());
+        let mut expressions = self.parser.parse().map_err(|error| RuntimeError::Parser {
+            location: parser_error_span(&error, &tokens, &offsets, &source.text)
+                .map(|span| Location::new(source.clone(), span)),
+            error: Box::new(error),
+        })?;
+        self.resolve_aliases(&mut expressions);
+
+        Ok(expressions)
+    }
+
+    /// Parse a source and hand the lexer and parser over to the compiler, which reads
+    /// the text of literals and patterns through its own.
+    fn parse_for_compiler(
+        &mut self,
+        source: &Arc<Source>,
+    ) -> Result<Vec<Expression>, RuntimeError> {
+        let expressions = self.parse(source)?;
+        std::mem::swap(&mut self.lexer, &mut self.compiler.lexer);
+        std::mem::swap(&mut self.parser, &mut self.compiler.parser);
+        Ok(expressions)
+    }
+
+    /// Parse the given source without compiling or loading anything, to check whether
+    /// its syntax is valid.
+    pub fn check_syntax(&mut self, source: Source) -> Result<(), RuntimeError> {
+        self.parse(&Arc::new(source)).map(|_| ())
+    }
+
+    /// Compile the given source together with any modules it imports which weren't
+    /// loaded before, recording where each expression's instructions end up in the
+    /// source map.
+    ///
+    /// The instructions are placed at the next free address, so they have to be pushed
+    /// to the machine before anything else is compiled.
+    fn compile(&mut self, source: Source) -> Result<Vec<strontium::Instruction>, RuntimeError> {
+        let source = Arc::new(source);
+        let imports = self.imports(&source);
+
+        // If anything fails, none of the instructions reach the machine, so modules
+        // compiled along the way have to be compiled again by the next import.
+        let checkpoint = self.checkpoint();
+        let result = self.in_script(&source, |runtime| {
+            let mut bytecode = runtime.load_imports(&source, &imports)?;
+            bytecode.append(&mut runtime.compile_expressions(&source, None)?);
+            Ok(bytecode)
+        });
+
+        if result.is_err() {
+            self.rollback(checkpoint);
+        }
+
+        result
+    }
+
+    /// Compile the given source without running it, returning every error found.
+    ///
+    /// Unlike [`Runtime::compile`], this carries on with the next top-level expression
+    /// after one fails to compile, so all of them are reported at once. A parse error
+    /// or a module which can't be imported still ends the check, since nothing after it
+    /// can be compiled reliably.
+    pub fn check(&mut self, source: Source) -> Vec<RuntimeError> {
+        let source = Arc::new(source);
+        let imports = self.imports(&source);
+
+        let checkpoint = self.checkpoint();
+        let errors = self.in_script(&source, |runtime| {
+            if let Err(e) = runtime.load_imports(&source, &imports) {
+                return vec![e];
+            }
+
+            let expressions = match runtime.parse_for_compiler(&source) {
+                Ok(expressions) => expressions,
+                Err(e) => return vec![e],
+            };
+
+            let offsets = char_offsets(&runtime.compiler.lexer);
+            let errors = expressions
+                .into_iter()
+                .filter_map(|mut expression| {
+                    let location = Location::new(
+                        source.clone(),
+                        char_span(&offsets, expression.start_pos, expression.end_pos),
+                    );
+
+                    expression.desugar();
+                    runtime.compile_top_level(expression, &location).err()
+                })
+                .collect();
+            runtime.compiler.compiled_methods.clear();
+            errors
+        });
+        self.rollback(checkpoint);
+
+        errors
+    }
+
+    /// Run a compilation step for the given source while it is on the import stack.
+    fn in_script<T>(&mut self, source: &Source, step: impl FnOnce(&mut Self) -> T) -> T {
+        // A script which is imported by one of its own modules is part of a cycle too.
+        let script = module::source_path(&source.name).map(|path| Module {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| source.name.clone()),
+            path: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+        });
+        let pushed = script.is_some();
+        self.import_stack.extend(script);
+
+        let result = step(self);
+
+        if pushed {
+            self.import_stack.pop();
+        }
+
+        result
+    }
+
+    /// Remember how much code was compiled so far, so compilation can be undone.
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            next_address: self.next_address,
+            source_map: self.source_map.len(),
+            modules: self.modules.len(),
+            module_aliases: self.module_aliases.len(),
+            defined_methods: self.defined_methods.len(),
+        }
+    }
+
+    /// Forget everything compiled since the given checkpoint.
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.next_address = checkpoint.next_address;
+        self.source_map.truncate(checkpoint.source_map);
+        self.modules.truncate(checkpoint.modules);
+        self.module_aliases.truncate(checkpoint.module_aliases);
+        self.defined_methods.truncate(checkpoint.defined_methods);
+    }
+
+    /// Compile the expressions of a source whose imports were already loaded, linking
+    /// them into a block of instructions which starts at the next free address.
+    ///
+    /// The block starts with the bodies of the multimethods the source defines, behind
+    /// a jump to the top-level code, and ends with a `HALT` instruction. The methods of
+    /// a module are defined under their qualified names.
+    fn compile_expressions(
+        &mut self,
+        source: &Arc<Source>,
+        module: Option<&Module>,
+    ) -> Result<Vec<strontium::Instruction>, RuntimeError> {
+        let mut expressions = self.parse_for_compiler(source)?;
+
+        if let Some(module) = module {
+            module::qualify_methods(module, &mut expressions);
+        }
+
+        let result = self.compile_main(source, expressions);
+        // Method bodies are only placed once the whole source compiled.
+        let mut methods: Vec<_> = self
+            .compiler
+            .compiled_methods
+            .drain()
+            .map(|(_, method)| method)
+            .collect();
+        let (mut main, spans, method_locations) = result?;
+        methods.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut bytecode = vec![];
+        let mut address = self.next_address;
+
+        if !methods.is_empty() {
+            address += link::encoded_size(&strontium::Instruction::Jump { destination: 0 });
+        }
+
+        for method in methods {
+            let size = link::size_of(&method.instructions);
+
+            if let Some(location) = method_locations.get(&method.id) {
+                self.source_map
+                    .insert(address..address + size, location.clone());
+            }
+
+            self.defined_methods.push(MethodEntry {
+                name: method.method_name,
+                pattern: method.pattern,
+                address,
+            });
+            bytecode.extend(link::resolve_labels(method.instructions, address)?);
+            address += size;
+        }
+
+        if !bytecode.is_empty() {
+            bytecode.insert(
+                0,
+                strontium::Instruction::Jump {
+                    destination: address as u32,
+                },
+            );
+        }
+
+        for (range, location) in spans {
+            self.source_map
+                .insert(address + range.start..address + range.end, location);
+        }
+
+        main.push(strontium::Instruction::Halt);
+        let size = link::size_of(&main);
+        bytecode.extend(link::resolve_labels(main, address)?);
+        self.next_address = address + size;
+
+        Ok(bytecode)
+    }
+
+    /// Compile the top-level expressions of a source, returning their instructions, the
+    /// range of instructions each expression was compiled to relative to the first one,
+    /// and the location of each multimethod definition.
+    #[allow(clippy::type_complexity)]
+    fn compile_main(
+        &mut self,
+        source: &Arc<Source>,
+        expressions: Vec<Expression>,
+    ) -> Result<
+        (
+            Vec<strontium::Instruction>,
+            Vec<(Range<usize>, Location)>,
+            HashMap<String, Location>,
+        ),
+        RuntimeError,
+    > {
+        let mut bytecode = vec![];
+        let mut spans = vec![];
+        let mut method_locations = HashMap::new();
+        let mut size = 0;
+
+        let offsets = char_offsets(&self.compiler.lexer);
+
+        for mut expression in expressions {
+            let location = Location::new(
+                source.clone(),
+                char_span(&offsets, expression.start_pos, expression.end_pos),
+            );
+
+            expression.desugar();
+
+            if let ExpressionKind::Method(method) = &expression.kind {
+                method_locations.insert(
+                    Compiler::generate_method_id(&method.name, &method.signature),
+                    location.clone(),
+                );
+            }
+
+            let compiled = self.compile_top_level(expression, &location)?;
+            let compiled_size = link::size_of(&compiled);
+            spans.push((size..size + compiled_size, location));
+            size += compiled_size;
+            bytecode.extend(compiled);
+        }
+
+        Ok((bytecode, spans, method_locations))
+    }
+
+    /// Compile a single desugared top-level expression, whose result goes to the `ret`
+    /// register where the result of a run is read from.
+    fn compile_top_level(
+        &mut self,
+        expression: Expression,
+        location: &Location,
+    ) -> Result<Vec<strontium::Instruction>, RuntimeError> {
+        self.compiler
+            .compile_expression(expression, Some("ret".to_string()))
+            .map_err(|error| RuntimeError::Compiler {
+                error: Box::new(error),
+                location: Some(location.clone()),
+            })
+    }
+
+    /// Compile the given source and execute it until the end of the instruction stream.
+    ///
+    /// This is the single pipeline shared by file, stdin and REPL execution, so a
+    /// program behaves the same no matter how it reaches the runtime.
+    pub fn run(&mut self, source: Source, cancellation: &CancellationToken) -> RunOutcome {
+        let start = self.next_address;
+        let instructions = match self.compile(source) {
+            Ok(instructions) => instructions,
+            Err(e) => return RunOutcome::Error(e),
+        };
+
+        if self.config.debug {
+            let listing = self.disassemble(&instructions, start);
+            self.debug(DebugEvent::Compiled { listing: &listing });
+        }
+
+        self.execute(instructions, cancellation, self.config.timeout)
+    }
+
+    /// Compile the given source into a bytecode file which can be executed later
+    /// without recompiling it.
+    ///
+    /// The file contains the whole program, including any code the runtime loaded
+    /// before, like the Mag part of the prelude. The source itself isn't loaded into
+    /// the runtime.
+    pub fn build(&mut self, source: Source) -> Result<BytecodeFile, RuntimeError> {
+        let checkpoint = self.checkpoint();
+        let instructions = self.compile(source)?;
+        let mut program = self.program.clone();
+        program.extend(instructions);
+        let methods = self.methods().collect();
+        self.rollback(checkpoint);
 
-        self.parser.add_tokens(source, tokens);
-        self.parser.parse()
+        Ok(BytecodeFile {
+            methods,
+            instructions: program,
+            prelude: self.config.prelude,
+        })
     }
 
-    pub fn compile(&mut self, source: String) -> Result<Vec<strontium::Instruction>, String> {
-        self.lexer.add_text(source.clone());
-        let tokens = self.lexer.parse();
+    /// Compile the given source and render its instructions like
+    /// [`Runtime::disassemble`], without loading it into the runtime.
+    pub fn disassemble_source(&mut self, source: Source) -> Result<String, RuntimeError> {
+        let checkpoint = self.checkpoint();
+        let start = self.next_address;
+        let instructions = self.compile(source)?;
+        let listing = self.disassemble(&instructions, start);
+        self.rollback(checkpoint);
 
-        self.parser.add_tokens(source, tokens);
-        let expressions = self.parser.parse();
+        Ok(listing)
+    }
+
+    /// Execute a program loaded from a bytecode file.
+    ///
+    /// Since the file contains a whole program starting at address zero, it replaces
+    /// any code which was compiled or loaded before. Native methods stay registered.
+    ///
+    /// The file has to be built with the prelude if and only if the runtime loaded it.
+    pub fn run_bytecode(
+        &mut self,
+        file: BytecodeFile,
+        cancellation: &CancellationToken,
+    ) -> RunOutcome {
+        if file.prelude != self.config.prelude {
+            return RunOutcome::Error(RuntimeError::PreludeMismatch {
+                built_with_prelude: file.prelude,
+            });
+        }
+
+        self.compiler = Compiler::new();
+        self.machine = Strontium::new(self.config.debug);
+        self.registered_methods.clear();
+        self.program.clear();
+        self.end_address = 0;
+        self.print_points.clear();
+        self.source_map = SourceMap::new();
+        self.modules.clear();
+        self.module_aliases.clear();
+        self.defined_methods = file.methods;
+
+        if self.config.debug {
+            let listing = self.disassemble(&file.instructions, 0);
+            self.debug(DebugEvent::Compiled { listing: &listing });
+        }
+
+        self.push(file.instructions);
+        self.next_address = self.end_address;
+        let end = self.end_address;
+
+        // The entry points of the native methods are placed behind the program.
+        if let Err(e) = self.reinstall_natives() {
+            return RunOutcome::Error(e);
+        }
+
+        self.register_methods();
+        self.execute_range(0, end, cancellation, self.config.timeout)
+    }
+
+    /// Every multimethod implementation known to the runtime, whether it was compiled
+    /// from source or loaded from a bytecode file. Methods defined in modules appear
+    /// under their qualified name.
+    pub fn methods(&self) -> impl Iterator<Item = MethodEntry> + '_ {
+        self.defined_methods.iter().cloned()
+    }
+
+    /// Push the given instructions and execute them until the end of the instruction
+    /// stream, stopping them once they run longer than the time limit.
+    ///
+    /// Only the new instructions are executed. Everything which ran before stays live in
+    /// the machine, so earlier definitions remain available without replaying their side
+    /// effects.
+    pub(super) fn execute(
+        &mut self,
+        instructions: Vec<strontium::Instruction>,
+        cancellation: &CancellationToken,
+        timeout: Option<Duration>,
+    ) -> RunOutcome {
+        self.register_methods();
+
+        if instructions.is_empty() {
+            return RunOutcome::Value(Value::Empty);
+        }
+
+        let start = self.end_address;
+        self.push(instructions);
+        let end = self.end_address;
+
+        self.execute_range(start, end, cancellation, timeout)
+    }
+
+    /// Execute the instructions from the given address until execution reaches the end
+    /// address, calling native methods whenever the machine halts at one of their
+    /// entry points.
+    fn execute_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        cancellation: &CancellationToken,
+        timeout: Option<Duration>,
+    ) -> RunOutcome {
+        // A previous run may have stopped in the middle of a call after an error.
+        self.machine.call_stack.clear();
+        self.machine.bytecode_parser.index = start;
+        self.machine.registers.set("ret", RegisterValue::Empty);
+        cancellation.reset();
+
+        let watchdog = timeout.map(|limit| Watchdog::start(limit, cancellation));
+        let result = self.execute_until(end, cancellation);
+        let timed_out = watchdog.is_some_and(Watchdog::stop);
+
+        match result {
+            Ok(()) => RunOutcome::Value(
+                self.machine
+                    .registers
+                    .get("ret")
+                    .map(Value::from)
+                    .unwrap_or(Value::Empty),
+            ),
+            Err(RuntimeError::Machine { error, .. })
+                if matches!(*error, StrontiumError::Interrupted) =>
+            {
+                cancellation.reset();
+
+                match timeout {
+                    Some(limit) if timed_out => RunOutcome::Error(RuntimeError::TimedOut(limit)),
+                    _ => RunOutcome::Interrupted,
+                }
+            }
+            Err(e) => RunOutcome::Error(e),
+        }
+    }
+
+    fn execute_until(
+        &mut self,
+        end: usize,
+        cancellation: &CancellationToken,
+    ) -> Result<(), RuntimeError> {
+        loop {
+            let address = self.machine.bytecode_parser.index;
+
+            if cancellation.is_cancelled() {
+                self.machine.abort_execution();
+                return Err(self.machine_error(StrontiumError::Interrupted));
+            }
 
-        match expressions {
-            Ok(expressions) => {
-                let mut bytecode = vec![];
+            if let Some((register, next)) = self.print_points.get(&address) {
+                let value = self.machine.registers.get(register).map(Value::from);
 
-                for expression in expressions {
-                    let compiled = self.compiler.compile_expression(expression, None);
+                // The machine itself prints nothing for empty registers either.
+                if let Some(value) = value.filter(|value| !value.is_empty()) {
+                    write_output(&self.output_handler, &format!("{}\n", value));
+                }
+
+                self.machine.bytecode_parser.index = *next;
+                continue;
+            }
 
-                    match compiled {
-                        Ok(mut compiled) => {
-                            bytecode.append(&mut compiled);
-                        }
-                        Err(e) => {
-                            return Err(format!("{:?}", e));
-                        }
+            match self.machine.execute() {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(error) => return Err(self.machine_error(error)),
+            }
+
+            // The machine stops at a `HALT` instruction without moving past it. It is
+            // either the entry point of a native method or the end of a compiled source,
+            // which may be followed by more code to run.
+            let address = self.machine.bytecode_parser.index;
+
+            match self.native_stubs.get(&address) {
+                Some(&native) => self.call_native(native)?,
+                None => {
+                    let next = address + link::encoded_size(&strontium::Instruction::Halt);
+
+                    if next >= end {
+                        return Ok(());
                     }
+
+                    self.machine.bytecode_parser.index = next;
                 }
+            }
+        }
+    }
+
+    fn machine_error(&self, error: StrontiumError) -> RuntimeError {
+        let backtrace = self.backtrace();
+
+        RuntimeError::Machine {
+            location: backtrace.iter().find_map(|frame| frame.location.clone()),
+            backtrace,
+            error: Box::new(error),
+        }
+    }
+
+    /// Push instructions to the end of the machine's instruction stream.
+    fn push(&mut self, instructions: Vec<strontium::Instruction>) {
+        for instruction in instructions {
+            let address = self.end_address;
+            self.end_address += link::encoded_size(&instruction);
 
-                Ok(bytecode)
+            if let strontium::Instruction::Interrupt {
+                interrupt:
+                    Interrupt {
+                        address: register,
+                        kind: InterruptKind::Print,
+                    },
+            } = &instruction
+            {
+                self.print_points
+                    .insert(address, (register.clone(), self.end_address));
             }
-            Err(e) => Err(format!("{:?}", e)),
+
+            self.program.push(instruction.clone());
+            self.machine.push_instruction(instruction);
         }
     }
 
-    pub fn execute_source(&mut self, source: String) {
-        let expressions_result = self.compile(source);
+    /// Build a Mag-level backtrace from the instruction the machine stopped at and the
+    /// return addresses of the multimethod calls which were still in progress.
+    fn backtrace(&self) -> Vec<Frame> {
+        let ip = self.machine.bytecode_parser.index;
+        // The machine stops at the entry point of a native method, but has already read
+        // any other instruction it stopped at, so the last byte before the instruction
+        // pointer belongs to the failing instruction.
+        let current = if self.native_stubs.contains_key(&ip) {
+            ip
+        } else {
+            ip.saturating_sub(1)
+        };
 
-        match expressions_result {
-            Ok(instructions) => {
-                // println!("{}\n{:#?}", "instructions:".bright_blue().bold(), instructions);
+        // Each return address points just past the call which created the frame.
+        std::iter::once(current)
+            .chain(
+                self.machine
+                    .call_stack
+                    .iter()
+                    .rev()
+                    .map(|frame| frame.return_address.saturating_sub(1)),
+            )
+            .map(|address| Frame {
+                address,
+                method: self.method_at(address),
+                location: self.source_map.lookup(address).cloned(),
+            })
+            .collect()
+    }
 
-                for instruction in instructions {
-                    self.machine.push_instruction(instruction);
-                }
+    /// Find the multimethod implementation whose body contains the given address.
+    fn method_at(&self, address: usize) -> Option<MethodFrame> {
+        if let Some(native) = self.native_at(address) {
+            return Some(MethodFrame {
+                name: native.name.clone(),
+                pattern: format_pattern(&native.pattern),
+            });
+        }
+
+        let range = self.source_map.range(address)?;
 
-                self.machine.execute().unwrap();
+        self.methods()
+            .filter(|method| range.contains(&method.address) && method.address <= address)
+            .max_by_key(|method| method.address)
+            .map(|method| MethodFrame {
+                name: method.name,
+                pattern: format_pattern(&method.pattern),
+            })
+    }
+
+    /// Make the given command line arguments available to Mag code through `args()`,
+    /// which returns them as a list of strings.
+    pub fn set_args(&mut self, args: &[String]) -> Result<(), RuntimeError> {
+        let args = Value::List(args.iter().cloned().map(Value::String).collect());
+        self.register_native("args()", move |_| Ok(args.clone()))
+    }
+
+    /// Add the multimethods defined since the last run to the VM's dispatch table.
+    fn register_methods(&mut self) {
+        let methods: Vec<MethodEntry> = self
+            .methods()
+            .filter(|method| {
+                !self
+                    .registered_methods
+                    .contains(&(method.name.clone(), method.address))
+            })
+            .collect();
+
+        for method in methods {
+            self.registered_methods
+                .insert((method.name.clone(), method.address));
+
+            if self.config.debug {
+                self.debug(DebugEvent::MethodRegistered { method: &method });
             }
-            Err(e) => {
-                println!("{} {:?}", "error:".bright_red().bold(), e);
+
+            self.machine
+                .register_method(method.name, method.pattern, method.address);
+        }
+    }
+}
+
+/// Create a lexer for a source.
+fn new_lexer(source: String) -> Lexer {
+    let mut lexer = Lexer::new();
+    lexer.add_text(source);
+
+    // The lexer looks at the character after operators like `/` or `+` without checking
+    // for the end of the input, which input being typed often ends in.
+    lexer.source.push(" ".to_string());
+    lexer
+}
+
+/// Pass text printed by a script to the output handler.
+fn write_output(handler: &OutputHandler, text: &str) {
+    if let Some(handler) = handler.borrow_mut().as_mut() {
+        handler(text);
+    }
+}
+
+/// Find the part of the source which caused a parser error, using the positions of
+/// the tokens produced by the lexer.
+fn parser_error_span(
+    error: &ParserError,
+    tokens: &[Token],
+    offsets: &[usize],
+    source: &str,
+) -> Option<Span> {
+    let span = |token: &Token| char_span(offsets, token.start_pos, token.end_pos);
+
+    match error {
+        ParserError::UnexpectedToken { found, .. } => Some(span(found)),
+        ParserError::MissingPrefixParselet(kind) => {
+            tokens.iter().find(|token| &token.kind == kind).map(span)
+        }
+        ParserError::UnexpectedEOF => {
+            let end = source.trim_end().chars().count();
+            Some(Span::new(end, end))
+        }
+        _ => tokens.last().map(span),
+    }
+}
+
+/// How much code was compiled at some point, see [`Runtime::checkpoint`].
+struct Checkpoint {
+    next_address: usize,
+    source_map: usize,
+    modules: usize,
+    module_aliases: usize,
+    defined_methods: usize,
+}
+
+/// A thread which cancels a run through its cancellation token once the time limit
+/// has passed.
+struct Watchdog {
+    done: mpsc::Sender<()>,
+    timed_out: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Watchdog {
+    fn start(limit: Duration, cancellation: &CancellationToken) -> Self {
+        let (done, finished) = mpsc::channel();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let flag = cancellation.flag();
+        let expired = timed_out.clone();
+
+        let thread = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = finished.recv_timeout(limit) {
+                expired.store(true, Ordering::SeqCst);
+                flag.store(true, Ordering::SeqCst);
             }
+        });
+
+        Self {
+            done,
+            timed_out,
+            thread,
         }
     }
+
+    /// Stop watching the run, returning true if it was cancelled for taking too long.
+    fn stop(self) -> bool {
+        let _ = self.done.send(());
+        let _ = self.thread.join();
+        self.timed_out.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runtime(prelude: bool) -> Runtime {
+        Runtime::new(RuntimeConfig {
+            prelude,
+            ..RuntimeConfig::default()
+        })
+        .unwrap()
+    }
+
+    fn run(runtime: &mut Runtime, text: &str) -> RunOutcome {
+        runtime.run(Source::new("<test>", text), &CancellationToken::new())
+    }
+
+    #[test]
+    fn runs_source_to_a_value() {
+        let mut runtime = runtime(false);
+
+        assert!(matches!(
+            run(&mut runtime, "1 + 2"),
+            RunOutcome::Value(Value::Int(3))
+        ));
+        assert!(matches!(
+            run(&mut runtime, ""),
+            RunOutcome::Value(Value::Empty)
+        ));
+    }
+
+    #[test]
+    fn keeps_definitions_between_runs() {
+        let mut runtime = runtime(false);
+
+        assert!(matches!(
+            run(&mut runtime, "def double(n Int) n * 2"),
+            RunOutcome::Value(_)
+        ));
+        assert!(matches!(
+            run(&mut runtime, "double(21)"),
+            RunOutcome::Value(Value::Int(42))
+        ));
+        assert_eq!(
+            runtime
+                .methods()
+                .map(|method| method.name)
+                .collect::<Vec<_>>(),
+            ["double"]
+        );
+    }
+
+    #[test]
+    fn discards_sources_which_fail_to_compile() {
+        let mut runtime = runtime(false);
+        let address = runtime.next_address;
+
+        match run(&mut runtime, "def double(n Int) n * 2\ndouble(") {
+            RunOutcome::Error(e) => assert!(e.is_compile_error()),
+            _ => panic!("expected a compile error"),
+        }
+        assert_eq!(runtime.next_address, address);
+        assert_eq!(runtime.methods().count(), 0);
+    }
+
+    #[test]
+    fn passes_output_to_the_handler() {
+        let mut runtime = runtime(true);
+        let output = Rc::new(RefCell::new(String::new()));
+        let sink = output.clone();
+        runtime.on_output(move |text| sink.borrow_mut().push_str(text));
+
+        run(&mut runtime, "println(\"one\")\nprint(2)");
+
+        assert_eq!(output.borrow().as_str(), "one\n2");
+    }
 }
@@ -0,0 +1,567 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use magc::lexer::Lexer;
+use magc::types::{Expression, ExpressionKind, Keyword, Pattern, Token, TokenKind};
+use strontium::Instruction;
+
+use super::source::{char_offsets, char_span};
+use super::{Location, Runtime, RuntimeError, Source, Span};
+
+/// The file extension of Mag source files, which module names resolve to.
+pub const SOURCE_EXTENSION: &str = "mag";
+
+/// An `import` statement found in a source file.
+#[derive(Clone, Debug)]
+pub struct Import {
+    /// The dotted module name, like `foo.bar`.
+    pub name: String,
+    /// The whole statement within the importing source.
+    pub span: Span,
+}
+
+/// A module which was loaded through an `import` statement.
+#[derive(Clone, Debug)]
+pub struct Module {
+    pub name: String,
+    /// The canonical path of the module's source file.
+    pub path: PathBuf,
+}
+
+/// Read the module search path from the `MAG_PATH` environment variable, which uses
+/// the platform's separator for lists of paths.
+pub fn search_path_from_env() -> Vec<PathBuf> {
+    env::var_os("MAG_PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+impl Runtime {
+    /// Every module loaded so far, in the order their code was compiled.
+    pub fn modules(&self) -> impl Iterator<Item = &Module> {
+        self.modules.iter()
+    }
+
+    /// Find the modules a source imports.
+    pub(super) fn imports(&mut self, source: &Source) -> Vec<Import> {
+        let tokens = self.lex(source.text.clone());
+        take_imports(tokens, &self.lexer).1
+    }
+
+    /// Compile the modules imported by a source which weren't loaded before, returning
+    /// their instructions.
+    pub(super) fn load_imports(
+        &mut self,
+        source: &Arc<Source>,
+        imports: &[Import],
+    ) -> Result<Vec<Instruction>, RuntimeError> {
+        let mut bytecode = vec![];
+
+        for import in imports {
+            let location = Location::new(source.clone(), import.span);
+            let path = self.resolve(&import.name, &source.name, &location)?;
+            self.claim_name(&import.name, &path, &location)?;
+
+            if let Some(loaded) = self.modules.iter().find(|module| module.path == path) {
+                let known = loaded.name == import.name
+                    || self
+                        .module_aliases
+                        .iter()
+                        .any(|alias| alias.name == import.name);
+
+                if !known {
+                    self.module_aliases.push(Module {
+                        name: import.name.clone(),
+                        path,
+                    });
+                }
+                continue;
+            }
+
+            if let Some(position) = self
+                .import_stack
+                .iter()
+                .position(|module| module.path == path)
+            {
+                let mut cycle: Vec<String> = self.import_stack[position..]
+                    .iter()
+                    .map(|module| module.name.clone())
+                    .collect();
+                cycle.push(import.name.clone());
+
+                return Err(RuntimeError::ImportCycle { cycle, location });
+            }
+
+            let text =
+                fs::read_to_string(&path).map_err(|error| RuntimeError::ModuleUnreadable {
+                    name: import.name.clone(),
+                    path: path.clone(),
+                    error,
+                    location: location.clone(),
+                })?;
+
+            let module = Module {
+                name: import.name.clone(),
+                path,
+            };
+
+            self.import_stack.push(module.clone());
+            let result = self.compile_module(&module, text);
+            self.import_stack.pop();
+
+            bytecode.append(&mut result?);
+            // A module imported while this one was compiled may have taken its name.
+            self.claim_name(&module.name, &module.path, &location)?;
+            self.modules.push(module);
+        }
+
+        Ok(bytecode)
+    }
+
+    /// Make sure a module name isn't already used by a module loaded from another file,
+    /// since both would define their multimethods under the same qualified names.
+    fn claim_name(&self, name: &str, path: &Path, location: &Location) -> Result<(), RuntimeError> {
+        let existing = self
+            .modules
+            .iter()
+            .chain(&self.module_aliases)
+            .find(|module| module.name == name && module.path != path);
+
+        match existing {
+            Some(existing) => Err(RuntimeError::ModuleNameClash {
+                name: name.to_string(),
+                path: path.to_path_buf(),
+                existing: existing.path.clone(),
+                location: location.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Compile a module and its own imports. The multimethods of the module are only
+    /// defined under their qualified names, like `foo.bar.baz`.
+    fn compile_module(
+        &mut self,
+        module: &Module,
+        text: String,
+    ) -> Result<Vec<Instruction>, RuntimeError> {
+        let source = Arc::new(Source::new(module.path.display().to_string(), text));
+        let imports = self.imports(&source);
+
+        let mut bytecode = self.load_imports(&source, &imports)?;
+        bytecode.append(&mut self.compile_expressions(&source, Some(module))?);
+
+        Ok(bytecode)
+    }
+
+    /// Rename calls through another spelling of a loaded module, like `lib.util.shout`
+    /// for the module loaded as `util`, to the name the method was defined under.
+    pub(super) fn resolve_aliases(&self, expressions: &mut [Expression]) {
+        if self.module_aliases.is_empty() {
+            return;
+        }
+
+        let resolve = |name: &mut String| {
+            let resolved = name.rsplit_once('.').and_then(|(prefix, method)| {
+                let alias = self
+                    .module_aliases
+                    .iter()
+                    .find(|alias| alias.name == prefix)?;
+                let module = self
+                    .modules
+                    .iter()
+                    .find(|module| module.path == alias.path)?;
+
+                Some(format!("{}.{}", module.name, method))
+            });
+
+            if let Some(resolved) = resolved {
+                *name = resolved;
+            }
+        };
+
+        for expression in expressions {
+            rename_in_expression(expression, &resolve);
+        }
+    }
+
+    /// Find the file a module name refers to, looking next to the importing source
+    /// first and then in each directory of the search path.
+    fn resolve(
+        &self,
+        name: &str,
+        importer: &str,
+        location: &Location,
+    ) -> Result<PathBuf, RuntimeError> {
+        let relative: PathBuf = name
+            .split('.')
+            .collect::<PathBuf>()
+            .with_extension(SOURCE_EXTENSION);
+
+        let base = source_path(importer)
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+            .unwrap_or_else(|| PathBuf::from("."));
+        let searched: Vec<PathBuf> = std::iter::once(base)
+            .chain(self.config.search_path.iter().cloned())
+            .map(|directory| directory.join(&relative))
+            .collect();
+
+        searched
+            .iter()
+            .find(|path| path.is_file())
+            .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
+            .ok_or_else(|| RuntimeError::ModuleNotFound {
+                name: name.to_string(),
+                searched,
+                location: location.clone(),
+            })
+    }
+}
+
+/// Returns the script a source was read from, or `None` for sources like `<stdin>` or
+/// `<repl:1>` which don't correspond to a file.
+pub fn source_path(name: &str) -> Option<&Path> {
+    if name.starts_with('<') {
+        None
+    } else {
+        Some(Path::new(name))
+    }
+}
+
+/// Remove the `import` statements from the tokens of a source, returning the modules
+/// it imports.
+///
+/// Only the keyword `import` followed by a dotted name is a statement, so text in
+/// strings and comments is never mistaken for one. An `import` without a name is left
+/// for the parser to report.
+pub(super) fn take_imports(tokens: Vec<Token>, lexer: &Lexer) -> (Vec<Token>, Vec<Import>) {
+    let offsets = char_offsets(lexer);
+    let mut imports = vec![];
+    let mut remaining = vec![];
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let token = &tokens[index];
+
+        if token.kind == TokenKind::Keyword(Keyword::Import) {
+            let segments = dotted_name(&tokens[index + 1..], false);
+
+            if let Some(last) = segments.last() {
+                imports.push(Import {
+                    name: join_segments(&segments, lexer),
+                    span: char_span(&offsets, token.start_pos, last.end_pos),
+                });
+                index += 2 * segments.len();
+                continue;
+            }
+        }
+
+        remaining.push(token.clone());
+        index += 1;
+    }
+
+    (remaining, imports)
+}
+
+/// Join references to the multimethods of the given modules, like `foo.bar.baz`, into
+/// single identifier tokens, so they are parsed as calls to the qualified name rather
+/// than as member accesses.
+///
+/// Only names written without whitespace around the dots whose leading segments name
+/// one of the modules are joined.
+pub(super) fn qualify_references(
+    tokens: Vec<Token>,
+    lexer: &Lexer,
+    modules: &[String],
+) -> Vec<Token> {
+    let mut qualified = vec![];
+    let mut index = 0;
+
+    while index < tokens.len() {
+        let segments = dotted_name(&tokens[index..], true);
+
+        // The last segment is the method, and the ones before it the longest module name.
+        let method = (1..segments.len())
+            .rev()
+            .find(|&count| modules.contains(&join_segments(&segments[..count], lexer)));
+
+        match method {
+            Some(count) => {
+                qualified.push(Token {
+                    kind: TokenKind::Identifier,
+                    line: segments[0].line,
+                    start_pos: segments[0].start_pos,
+                    end_pos: segments[count].end_pos,
+                });
+                index += 2 * count + 1;
+            }
+            None => {
+                qualified.push(tokens[index].clone());
+                index += 1;
+            }
+        }
+    }
+
+    qualified
+}
+
+/// Collect the segments of a dotted name like `foo.bar` at the start of the tokens,
+/// optionally only while the dots are directly attached to the segments.
+fn dotted_name(tokens: &[Token], adjacent: bool) -> Vec<&Token> {
+    let mut segments: Vec<&Token> = vec![];
+
+    for pair in tokens.chunks(2) {
+        let segment = &pair[0];
+
+        if !matches!(segment.kind, TokenKind::Identifier | TokenKind::Type) {
+            break;
+        }
+        if adjacent
+            && segments
+                .last()
+                .is_some_and(|last| last.end_pos + 1 != segment.start_pos)
+        {
+            break;
+        }
+
+        segments.push(segment);
+
+        match pair.get(1) {
+            Some(dot)
+                if dot.kind == TokenKind::Dot
+                    && (!adjacent || dot.start_pos == segment.end_pos) => {}
+            _ => break,
+        }
+    }
+
+    segments
+}
+
+fn join_segments(segments: &[&Token], lexer: &Lexer) -> String {
+    segments
+        .iter()
+        .filter_map(|token| lexer.get_literal_string(token.start_pos, token.end_pos))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Rename the multimethods a module defines, and the calls to them within the module, to
+/// their qualified names, like `foo.bar.baz` for the method `baz` of module `foo.bar`.
+pub(super) fn qualify_methods(module: &Module, expressions: &mut [Expression]) {
+    let names: HashSet<String> = expressions
+        .iter()
+        .filter_map(|expression| match &expression.kind {
+            ExpressionKind::Method(method) => Some(method.name.clone()),
+            _ => None,
+        })
+        .collect();
+    let qualify = |name: &mut String| {
+        if names.contains(name) {
+            *name = format!("{}.{}", module.name, name);
+        }
+    };
+
+    for expression in expressions {
+        rename_in_expression(expression, &qualify);
+    }
+}
+
+fn rename_in_expression(expression: &mut Expression, rename: &impl Fn(&mut String)) {
+    match &mut expression.kind {
+        ExpressionKind::Method(method) => {
+            rename(&mut method.name);
+
+            if let Some(signature) = &mut method.signature {
+                rename_in_pattern(signature, rename);
+            }
+            rename_in_expression(&mut method.body, rename);
+        }
+        ExpressionKind::Call(call) => {
+            rename(&mut call.name);
+
+            if let Some(signature) = &mut call.signature {
+                rename_in_pattern(signature, rename);
+            }
+        }
+        ExpressionKind::Conditional(conditional) => {
+            rename_in_expression(&mut conditional.condition, rename);
+            rename_in_expression(&mut conditional.then_arm, rename);
+
+            if let Some(else_arm) = &mut conditional.else_arm {
+                rename_in_expression(else_arm, rename);
+            }
+        }
+        ExpressionKind::List(Some(child)) => rename_in_expression(child, rename),
+        ExpressionKind::Pattern(pattern) => rename_in_pattern(pattern, rename),
+        ExpressionKind::Prefix(prefix) => rename_in_expression(&mut prefix.operand, rename),
+        ExpressionKind::Infix(infix) => {
+            rename_in_expression(&mut infix.left, rename);
+            rename_in_expression(&mut infix.right, rename);
+        }
+        ExpressionKind::Block(block) => {
+            for child in block
+                .children
+                .iter_mut()
+                .chain(block.environment.values_mut())
+            {
+                rename_in_expression(child, rename);
+            }
+        }
+        ExpressionKind::Var(declaration) => rename_in_expression(&mut declaration.value, rename),
+        ExpressionKind::Return(ret) => rename_in_expression(&mut ret.value, rename),
+        ExpressionKind::List(None)
+        | ExpressionKind::Literal(_)
+        | ExpressionKind::Type(_)
+        | ExpressionKind::Identifier => {}
+    }
+}
+
+fn rename_in_pattern(pattern: &mut Pattern, rename: &impl Fn(&mut String)) {
+    match pattern {
+        Pattern::Field(field) => rename_in_pattern(&mut field.value, rename),
+        Pattern::Tuple(tuple) => rename_in_pattern(&mut tuple.child, rename),
+        Pattern::Value(value) => rename_in_expression(&mut value.expression, rename),
+        Pattern::Pair(pair) => {
+            rename_in_pattern(&mut pair.left, rename);
+            rename_in_pattern(&mut pair.right, rename);
+        }
+        Pattern::Variable(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{CancellationToken, RunOutcome, RuntimeConfig, Value};
+
+    fn lex(text: &str) -> (Vec<Token>, Lexer) {
+        let mut lexer = Lexer::new();
+        lexer.add_text(text.to_string());
+        let tokens = lexer.parse();
+        (tokens, lexer)
+    }
+
+    fn lexemes(tokens: &[Token], lexer: &Lexer) -> Vec<String> {
+        tokens
+            .iter()
+            .filter_map(|token| lexer.get_literal_string(token.start_pos, token.end_pos))
+            .collect()
+    }
+
+    #[test]
+    fn takes_import_statements() {
+        let (tokens, lexer) = lex("import foo.bar\nimport baz\nshout(1)");
+        let (tokens, imports) = take_imports(tokens, &lexer);
+
+        let names: Vec<&str> = imports.iter().map(|import| import.name.as_str()).collect();
+        assert_eq!(names, ["foo.bar", "baz"]);
+        assert_eq!(imports[0].span, Span::new(0, 14));
+        assert_eq!(lexemes(&tokens, &lexer), ["shout", "(", "1", ")"]);
+    }
+
+    #[test]
+    fn ignores_imports_in_strings() {
+        let (tokens, lexer) = lex("print(\"import foo\")");
+        let (remaining, imports) = take_imports(tokens.clone(), &lexer);
+
+        assert!(imports.is_empty());
+        assert_eq!(remaining, tokens);
+    }
+
+    #[test]
+    fn joins_references_to_module_methods() {
+        let (tokens, lexer) = lex("foo.bar.shout(1) + person.name");
+        let tokens = qualify_references(tokens, &lexer, &["foo.bar".to_string()]);
+
+        assert_eq!(
+            lexemes(&tokens, &lexer),
+            ["foo.bar.shout", "(", "1", ")", "+", "person", ".", "name"]
+        );
+        assert_eq!(tokens[0].kind, TokenKind::Identifier);
+    }
+
+    #[test]
+    fn keeps_references_with_spaces_apart() {
+        let (tokens, lexer) = lex("foo . shout(1)");
+        let qualified = qualify_references(tokens.clone(), &lexer, &["foo".to_string()]);
+
+        assert_eq!(qualified, tokens);
+    }
+
+    #[test]
+    fn calls_a_module_under_each_import_spelling() {
+        let root = env::temp_dir().join(format!("mag-aliases-{}", std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("lib/util.mag"), "def shout(n Int) n * 2").unwrap();
+        fs::write(
+            root.join("lib/twice.mag"),
+            "import util\ndef twice(n Int) util.shout(n) + 1",
+        )
+        .unwrap();
+        let script = root.join("main.mag");
+        let text = "import lib.twice\nimport lib.util\nlib.util.shout(lib.twice.twice(1))";
+
+        let mut runtime = Runtime::new(RuntimeConfig {
+            prelude: false,
+            ..RuntimeConfig::default()
+        })
+        .unwrap();
+        let outcome = runtime.run(
+            Source::new(script.display().to_string(), text),
+            &CancellationToken::new(),
+        );
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(matches!(outcome, RunOutcome::Value(Value::Int(6))));
+        let names: Vec<&str> = runtime
+            .modules()
+            .map(|module| module.name.as_str())
+            .collect();
+        assert_eq!(names, ["util", "lib.twice"]);
+    }
+
+    #[test]
+    fn rejects_modules_of_the_same_name_from_different_files() {
+        let root = env::temp_dir().join(format!("mag-clash-{}", std::process::id()));
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("util.mag"), "def shout(n Int) n * 2").unwrap();
+        fs::write(root.join("lib/util.mag"), "def shout(n Int) n * 3").unwrap();
+        fs::write(
+            root.join("lib/twice.mag"),
+            "import util\ndef twice(n Int) util.shout(n)",
+        )
+        .unwrap();
+        let script = root.join("main.mag");
+        let text = "import util\nimport lib.twice\nutil.shout(lib.twice.twice(1))";
+
+        let mut runtime = Runtime::new(RuntimeConfig {
+            prelude: false,
+            ..RuntimeConfig::default()
+        })
+        .unwrap();
+        let outcome = runtime.run(
+            Source::new(script.display().to_string(), text),
+            &CancellationToken::new(),
+        );
+        let lib_util = fs::canonicalize(root.join("lib/util.mag")).unwrap();
+        let util = fs::canonicalize(root.join("util.mag")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        match outcome {
+            RunOutcome::Error(RuntimeError::ModuleNameClash {
+                name,
+                path,
+                existing,
+                ..
+            }) => {
+                assert_eq!(name, "util");
+                assert_eq!(path, lib_util);
+                assert_eq!(existing, util);
+            }
+            _ => panic!("expected a module name clash"),
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use magc::compiler::CompiledMethod;
+use strontium::machine::instruction::DispatchPattern;
+use strontium::machine::register::RegisterValue;
+use strontium::types::StrontiumError;
+use strontium::Instruction;
+
+use super::{Location, Runtime, RuntimeError, Source, Span, Value};
+
+/// A Rust function which implements a multimethod, receiving the arguments matched by
+/// its pattern and returning either a value or an error message.
+pub type NativeFunction = Rc<dyn Fn(&[Value]) -> Result<Value, String>>;
+
+/// A multimethod implementation registered by the host application.
+#[derive(Clone)]
+pub struct NativeMethod {
+    pub name: String,
+    /// The signature the method was registered with, like `print(s String)`.
+    pub signature: String,
+    pub pattern: DispatchPattern,
+    /// The names of the parameters in the signature.
+    pub parameters: Vec<String>,
+    pub function: NativeFunction,
+}
+
+impl Runtime {
+    /// Register a Rust function as a multimethod implementation with the given
+    /// signature, like `print(s String)`.
+    ///
+    /// Native implementations take part in the same pattern-based dispatch as methods
+    /// defined in Mag, so a script may add more implementations of the same method.
+    /// A method with several parameters receives its arguments from a list.
+    pub fn register_native<F>(&mut self, signature: &str, function: F) -> Result<(), RuntimeError>
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        let method = self.declare_native(signature)?;
+
+        self.natives.push(NativeMethod {
+            name: method.method_name,
+            signature: signature.to_string(),
+            pattern: method.pattern,
+            parameters: method.parameter_names,
+            function: Rc::new(function),
+        });
+        self.install_native(self.natives.len() - 1);
+
+        Ok(())
+    }
+
+    /// Every multimethod implementation registered by the host application.
+    pub fn native_methods(&self) -> impl Iterator<Item = &NativeMethod> {
+        self.natives.iter()
+    }
+
+    /// Declare the native methods to a new compiler and machine again.
+    pub(super) fn reinstall_natives(&mut self) -> Result<(), RuntimeError> {
+        self.native_stubs.clear();
+
+        for index in 0..self.natives.len() {
+            let signature = self.natives[index].signature.clone();
+            self.declare_native(&signature)?;
+            self.install_native(index);
+        }
+
+        Ok(())
+    }
+
+    /// Place the entry point of a native method behind the instructions pushed so far
+    /// and add it to the VM's dispatch table.
+    ///
+    /// The entry point is a single `HALT` instruction, which stops the machine so the
+    /// runtime can call the Rust function in its place.
+    fn install_native(&mut self, index: usize) {
+        let address = self.end_address;
+        self.push(vec![Instruction::Halt]);
+        self.next_address = self.end_address;
+        self.native_stubs.insert(address, index);
+
+        let native = &self.natives[index];
+        self.machine
+            .register_method(native.name.clone(), native.pattern.clone(), address);
+    }
+
+    /// Find the native method whose entry point is at the given address.
+    pub(super) fn native_at(&self, address: usize) -> Option<&NativeMethod> {
+        self.native_stubs
+            .get(&address)
+            .map(|index| &self.natives[*index])
+    }
+
+    /// Call the native method the machine dispatched to with the value of the `arg`
+    /// register, then return to the caller like a `RETURN` instruction would.
+    pub(super) fn call_native(&mut self, index: usize) -> Result<(), RuntimeError> {
+        let native = self.natives[index].clone();
+        let argument = self
+            .machine
+            .registers
+            .get("arg")
+            .map(Value::from)
+            .unwrap_or(Value::Empty);
+
+        let args = match (native.parameters.len(), argument) {
+            (0, _) => vec![],
+            (1, argument) => vec![argument],
+            (count, Value::List(items)) if items.len() == count => items,
+            (count, _) => {
+                return Err(
+                    self.native_error(&native, format!("expected a list of {} arguments", count))
+                )
+            }
+        };
+
+        let value = (native.function)(&args)
+            .and_then(|value| RegisterValue::try_from(value).map_err(|e| e.to_string()))
+            .map_err(|message| self.native_error(&native, message))?;
+        self.machine.registers.set("ret", value);
+
+        let frame = match self.machine.call_stack.pop() {
+            Some(frame) => frame,
+            None => return Err(self.machine_error(StrontiumError::EmptyCallStack)),
+        };
+
+        self.machine.bytecode_parser.index = frame.return_address;
+
+        for (name, value) in frame.saved_registers {
+            self.machine.registers.set(&name, value);
+        }
+
+        Ok(())
+    }
+
+    fn native_error(&self, native: &NativeMethod, message: String) -> RuntimeError {
+        let backtrace = self.backtrace();
+
+        RuntimeError::Native {
+            method: native.name.clone(),
+            message,
+            location: backtrace.iter().find_map(|frame| frame.location.clone()),
+            backtrace,
+        }
+    }
+
+    /// Make a method signature known to the compiler by compiling a definition with it,
+    /// returning the compiled method without placing its body anywhere.
+    fn declare_native(&mut self, signature: &str) -> Result<CompiledMethod, RuntimeError> {
+        let text = format!("def {} 0\n", signature);
+        let length = text.chars().count();
+        let source = Arc::new(Source::new("<native>", text));
+        let expressions = self.parse_for_compiler(&source)?;
+
+        let result = expressions.into_iter().try_for_each(|expression| {
+            self.compiler
+                .compile_expression(expression, None)
+                .map(|_| ())
+                .map_err(|error| RuntimeError::Compiler {
+                    error: Box::new(error),
+                    location: Some(Location::new(source.clone(), Span::new(0, length))),
+                })
+        });
+        let mut methods: Vec<CompiledMethod> = self
+            .compiler
+            .compiled_methods
+            .drain()
+            .map(|(_, method)| method)
+            .collect();
+        result?;
+
+        match methods.pop() {
+            Some(method) if methods.is_empty() => Ok(method),
+            _ => Err(RuntimeError::InvalidSignature(signature.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::{Runtime, RuntimeConfig, RuntimeError, Value};
+
+    fn runtime() -> Runtime {
+        Runtime::new(RuntimeConfig {
+            prelude: false,
+            ..RuntimeConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn evaluates_source() {
+        let mut runtime = runtime();
+
+        runtime.eval("def square(n Int) n * n").unwrap();
+        assert_eq!(runtime.eval("square(12)").unwrap(), Value::Int(144));
+        assert!(runtime.eval("square(").is_err());
+    }
+
+    #[test]
+    fn calls_registered_natives() {
+        let mut runtime = runtime();
+        runtime
+            .register_native("shout(s String)", |args| match &args[0] {
+                Value::String(text) => Ok(Value::String(text.to_uppercase())),
+                _ => Err("expected a string".to_string()),
+            })
+            .unwrap();
+        runtime
+            .register_native("add(a Int, b Int)", |args| match args {
+                [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a + b)),
+                _ => Err("expected two integers".to_string()),
+            })
+            .unwrap();
+
+        assert_eq!(runtime.eval("shout(\"hey\")").unwrap(), Value::from("HEY"));
+        assert_eq!(runtime.eval("add(2, 3)").unwrap(), Value::Int(5));
+        assert_eq!(
+            runtime
+                .native_methods()
+                .map(|method| method.name.as_str())
+                .collect::<Vec<_>>(),
+            ["shout", "add"]
+        );
+    }
+
+    #[test]
+    fn dispatches_between_native_and_mag_implementations() {
+        let mut runtime = runtime();
+        runtime
+            .register_native("answer()", |_| Ok(Value::Int(42)))
+            .unwrap();
+        runtime
+            .register_native("shout(s String)", |args| match &args[0] {
+                Value::String(text) => Ok(Value::String(text.to_uppercase())),
+                _ => Err("expected a string".to_string()),
+            })
+            .unwrap();
+        runtime.eval("def shout(n Int) n * 10").unwrap();
+
+        assert_eq!(runtime.eval("answer()").unwrap(), Value::Int(42));
+        assert_eq!(runtime.eval("shout(\"hey\")").unwrap(), Value::from("HEY"));
+        assert_eq!(runtime.eval("shout(answer())").unwrap(), Value::Int(420));
+    }
+
+    #[test]
+    fn reports_errors_of_natives() {
+        let mut runtime = runtime();
+        runtime
+            .register_native("fail(n Int)", |_| Err("no luck".to_string()))
+            .unwrap();
+        runtime
+            .register_native("opaque(n Int)", |_| Ok(Value::Other("{}".to_string())))
+            .unwrap();
+
+        for (source, message) in [("fail(1)", "no luck"), ("opaque(1)", "found Object")] {
+            match runtime.eval(source) {
+                Err(RuntimeError::Native { message: error, .. }) => {
+                    assert!(error.contains(message), "{}", error)
+                }
+                result => panic!("expected a native error, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let result = runtime().register_native("42", |_| Ok(Value::Empty));
+
+        assert!(result.is_err());
+    }
+}
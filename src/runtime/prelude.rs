@@ -0,0 +1,449 @@
+use super::{
+    write_output, CancellationToken, OutputHandler, RunOutcome, Runtime, RuntimeError, Source,
+    Value,
+};
+
+/// The part of the prelude which is written in Mag itself.
+const PRELUDE: &str = include_str!("prelude.mag");
+
+/// The most items `range` creates, since the whole list is built at once.
+const MAX_RANGE_LENGTH: i64 = 1_000_000;
+
+type Native = fn(&[Value]) -> Result<Value, String>;
+
+/// The natively implemented part of the prelude, as pairs of signatures and functions.
+const NATIVES: &[(&str, Native)] = &[
+    // Strings
+    ("to_string(value)", to_string),
+    ("upper(s String)", upper),
+    ("lower(s String)", lower),
+    ("trim(s String)", trim),
+    ("concat(a String, b String)", concat),
+    ("split(s String, separator String)", split),
+    ("join(list, separator String)", join),
+    ("parse_int(s String)", parse_int),
+    ("parse_float(s String)", parse_float),
+    // Strings and lists
+    ("length(value)", length),
+    ("contains(value, item)", contains),
+    ("reverse(value)", reverse),
+    // Lists
+    ("first(list)", first),
+    ("last(list)", last),
+    ("get(list, index Int)", get),
+    ("push(list, item)", push),
+    ("range(start Int, stop Int)", range),
+    // Maps, represented as lists of `[key, value]` pairs
+    ("map_get(map, key)", map_get),
+    ("map_set(map, key, value)", map_set),
+    ("map_keys(map)", map_keys),
+    ("map_values(map)", map_values),
+    // Math
+    ("abs(n)", abs),
+    ("factorial(n Int)", factorial),
+    ("sqrt(n)", sqrt),
+    ("floor(n)", floor),
+    ("ceil(n)", ceil),
+    ("round(n)", round),
+    ("pow(base, exponent)", pow),
+    ("min(a, b)", min),
+    ("max(a, b)", max),
+];
+
+impl Runtime {
+    /// Register the native prelude methods and load the Mag part of the prelude.
+    ///
+    /// The prelude isn't subject to the time limit of the configuration, which is only
+    /// meant for the code of the user.
+    pub(super) fn load_prelude(&mut self) -> Result<(), RuntimeError> {
+        // Printing goes to the output handler, so these need access to it.
+        let output = self.output_handler.clone();
+        self.register_native("print(value)", move |args| print(&output, args))?;
+        let output = self.output_handler.clone();
+        self.register_native("println(value)", move |args| println(&output, args))?;
+
+        for (signature, function) in NATIVES {
+            self.register_native(signature, *function)?;
+        }
+
+        let instructions = self.compile(Source::new("<prelude>", PRELUDE))?;
+
+        match self.execute(instructions, &CancellationToken::new(), None) {
+            RunOutcome::Value(_) => Ok(()),
+            RunOutcome::Error(e) => Err(e),
+            RunOutcome::Interrupted => Err(RuntimeError::Interrupted),
+        }
+    }
+}
+
+fn print(output: &OutputHandler, args: &[Value]) -> Result<Value, String> {
+    write_output(output, &args[0].to_string());
+    Ok(Value::Empty)
+}
+
+fn println(output: &OutputHandler, args: &[Value]) -> Result<Value, String> {
+    write_output(output, &format!("{}\n", args[0]));
+    Ok(Value::Empty)
+}
+
+fn to_string(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(args[0].to_string()))
+}
+
+fn upper(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(string(&args[0])?.to_uppercase()))
+}
+
+fn lower(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(string(&args[0])?.to_lowercase()))
+}
+
+fn trim(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(string(&args[0])?.trim().to_string()))
+}
+
+fn concat(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::String(format!(
+        "{}{}",
+        string(&args[0])?,
+        string(&args[1])?
+    )))
+}
+
+fn split(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::List(
+        string(&args[0])?
+            .split(string(&args[1])?)
+            .map(Value::from)
+            .collect(),
+    ))
+}
+
+fn join(args: &[Value]) -> Result<Value, String> {
+    let parts: Vec<String> = list(&args[0])?.iter().map(Value::to_string).collect();
+    Ok(Value::String(parts.join(string(&args[1])?)))
+}
+
+fn parse_int(args: &[Value]) -> Result<Value, String> {
+    let text = string(&args[0])?;
+
+    text.trim()
+        .parse()
+        .map(Value::Int)
+        .map_err(|_| format!("can't parse {:?} as an integer", text))
+}
+
+fn parse_float(args: &[Value]) -> Result<Value, String> {
+    let text = string(&args[0])?;
+
+    text.trim()
+        .parse()
+        .map(Value::Float)
+        .map_err(|_| format!("can't parse {:?} as a float", text))
+}
+
+fn length(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(text) => Ok(Value::Int(text.chars().count() as i64)),
+        Value::List(items) => Ok(Value::Int(items.len() as i64)),
+        value => Err(type_error("a String or List", value)),
+    }
+}
+
+fn contains(args: &[Value]) -> Result<Value, String> {
+    match (&args[0], &args[1]) {
+        (Value::String(text), Value::String(part)) => Ok(Value::Bool(text.contains(part.as_str()))),
+        (Value::List(items), item) => Ok(Value::Bool(items.contains(item))),
+        (value, _) => Err(type_error("a String or List", value)),
+    }
+}
+
+fn reverse(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::String(text) => Ok(Value::String(text.chars().rev().collect())),
+        Value::List(items) => Ok(Value::List(items.iter().rev().cloned().collect())),
+        value => Err(type_error("a String or List", value)),
+    }
+}
+
+fn first(args: &[Value]) -> Result<Value, String> {
+    Ok(list(&args[0])?.first().cloned().unwrap_or(Value::Empty))
+}
+
+fn last(args: &[Value]) -> Result<Value, String> {
+    Ok(list(&args[0])?.last().cloned().unwrap_or(Value::Empty))
+}
+
+fn get(args: &[Value]) -> Result<Value, String> {
+    let items = list(&args[0])?;
+    let index = int(&args[1])?;
+
+    usize::try_from(index)
+        .ok()
+        .and_then(|index| items.get(index))
+        .cloned()
+        .ok_or_else(|| {
+            format!(
+                "index {} is out of bounds for a list of length {}",
+                index,
+                items.len()
+            )
+        })
+}
+
+fn push(args: &[Value]) -> Result<Value, String> {
+    let mut items = list(&args[0])?.to_vec();
+    items.push(args[1].clone());
+    Ok(Value::List(items))
+}
+
+fn range(args: &[Value]) -> Result<Value, String> {
+    let (start, stop) = (int(&args[0])?, int(&args[1])?);
+
+    if stop.saturating_sub(start) > MAX_RANGE_LENGTH {
+        return Err(format!(
+            "a range from {} to {} has more than {} items",
+            start, stop, MAX_RANGE_LENGTH
+        ));
+    }
+
+    Ok(Value::List((start..stop).map(Value::Int).collect()))
+}
+
+fn map_get(args: &[Value]) -> Result<Value, String> {
+    for (key, value) in pairs(&args[0])? {
+        if key == &args[1] {
+            return Ok(value.clone());
+        }
+    }
+
+    Ok(Value::Empty)
+}
+
+fn map_set(args: &[Value]) -> Result<Value, String> {
+    let mut entries: Vec<Value> = pairs(&args[0])?
+        .filter(|(key, _)| *key != &args[1])
+        .map(|(key, value)| Value::List(vec![key.clone(), value.clone()]))
+        .collect();
+
+    entries.push(Value::List(vec![args[1].clone(), args[2].clone()]));
+    Ok(Value::List(entries))
+}
+
+fn map_keys(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::List(
+        pairs(&args[0])?.map(|(key, _)| key.clone()).collect(),
+    ))
+}
+
+fn map_values(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::List(
+        pairs(&args[0])?.map(|(_, value)| value.clone()).collect(),
+    ))
+}
+
+fn abs(args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Int(n) => n
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or_else(|| "integer overflow".to_string()),
+        value => Ok(Value::Float(number(value)?.abs())),
+    }
+}
+
+fn factorial(args: &[Value]) -> Result<Value, String> {
+    let n = int(&args[0])?;
+
+    if n < 0 {
+        return Err(format!(
+            "can't take the factorial of the negative number {}",
+            n
+        ));
+    }
+
+    (1..=n)
+        .try_fold(1i64, |product, factor| product.checked_mul(factor))
+        .map(Value::Int)
+        .ok_or_else(|| "integer overflow".to_string())
+}
+
+fn sqrt(args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Float(number(&args[0])?.sqrt()))
+}
+
+fn floor(args: &[Value]) -> Result<Value, String> {
+    whole(number(&args[0])?.floor())
+}
+
+fn ceil(args: &[Value]) -> Result<Value, String> {
+    whole(number(&args[0])?.ceil())
+}
+
+fn round(args: &[Value]) -> Result<Value, String> {
+    whole(number(&args[0])?.round())
+}
+
+fn pow(args: &[Value]) -> Result<Value, String> {
+    match (&args[0], &args[1]) {
+        (Value::Int(base), Value::Int(exponent)) if *exponent >= 0 => u32::try_from(*exponent)
+            .ok()
+            .and_then(|exponent| base.checked_pow(exponent))
+            .map(Value::Int)
+            .ok_or_else(|| "integer overflow".to_string()),
+        (base, exponent) => Ok(Value::Float(number(base)?.powf(number(exponent)?))),
+    }
+}
+
+fn min(args: &[Value]) -> Result<Value, String> {
+    match (&args[0], &args[1]) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(*a.min(b))),
+        (a, b) => Ok(Value::Float(number(a)?.min(number(b)?))),
+    }
+}
+
+fn max(args: &[Value]) -> Result<Value, String> {
+    match (&args[0], &args[1]) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(*a.max(b))),
+        (a, b) => Ok(Value::Float(number(a)?.max(number(b)?))),
+    }
+}
+
+fn string(value: &Value) -> Result<&str, String> {
+    match value {
+        Value::String(text) => Ok(text),
+        value => Err(type_error("a String", value)),
+    }
+}
+
+fn list(value: &Value) -> Result<&[Value], String> {
+    match value {
+        Value::List(items) => Ok(items),
+        value => Err(type_error("a List", value)),
+    }
+}
+
+fn int(value: &Value) -> Result<i64, String> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        Value::UInt(n) => i64::try_from(*n).map_err(|_| "integer overflow".to_string()),
+        value => Err(type_error("an Int", value)),
+    }
+}
+
+fn number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Int(n) => Ok(*n as f64),
+        Value::UInt(n) => Ok(*n as f64),
+        Value::Float(n) => Ok(*n),
+        value => Err(type_error("a number", value)),
+    }
+}
+
+/// Convert a whole float to an `Int`, rather than saturating values which don't fit.
+fn whole(n: f64) -> Result<Value, String> {
+    // `i64::MAX` isn't exactly representable, so the upper bound is 2^63 itself.
+    if n.is_nan() {
+        Err("can't convert NaN to an Int".to_string())
+    } else if n >= -(2f64.powi(63)) && n < 2f64.powi(63) {
+        Ok(Value::Int(n as i64))
+    } else {
+        Err("integer overflow".to_string())
+    }
+}
+
+/// Iterate over the `[key, value]` pairs of a map.
+fn pairs(value: &Value) -> Result<impl Iterator<Item = (&Value, &Value)>, String> {
+    let entries = list(value)?;
+
+    for entry in entries {
+        if !matches!(entry, Value::List(pair) if pair.len() == 2) {
+            return Err(type_error("a list of [key, value] pairs", value));
+        }
+    }
+
+    Ok(entries.iter().filter_map(|entry| match entry {
+        Value::List(pair) => Some((&pair[0], &pair[1])),
+        _ => None,
+    }))
+}
+
+fn type_error(expected: &str, found: &Value) -> String {
+    format!("expected {}, found {}", expected, found.type_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::RuntimeConfig;
+
+    #[test]
+    fn abs_reports_overflow() {
+        assert_eq!(abs(&[Value::Int(-3)]), Ok(Value::Int(3)));
+        assert_eq!(abs(&[Value::Float(-1.5)]), Ok(Value::Float(1.5)));
+        assert!(abs(&[Value::Int(i64::MIN)]).is_err());
+    }
+
+    #[test]
+    fn range_is_capped() {
+        assert_eq!(
+            range(&[Value::Int(1), Value::Int(4)]),
+            Ok(Value::List(vec![
+                Value::Int(1),
+                Value::Int(2),
+                Value::Int(3)
+            ]))
+        );
+        assert_eq!(
+            range(&[Value::Int(4), Value::Int(1)]),
+            Ok(Value::List(vec![]))
+        );
+        assert!(range(&[Value::Int(i64::MIN), Value::Int(i64::MAX)]).is_err());
+    }
+
+    #[test]
+    fn factorial_rejects_negative_numbers() {
+        assert_eq!(factorial(&[Value::Int(0)]), Ok(Value::Int(1)));
+        assert_eq!(factorial(&[Value::Int(5)]), Ok(Value::Int(120)));
+        assert!(factorial(&[Value::Int(-1)]).is_err());
+        assert!(factorial(&[Value::Int(21)]).is_err());
+    }
+
+    #[test]
+    fn rounding_rejects_floats_outside_of_ints() {
+        assert_eq!(floor(&[Value::Float(-1.5)]), Ok(Value::Int(-2)));
+        assert_eq!(ceil(&[Value::Float(1.2)]), Ok(Value::Int(2)));
+        assert_eq!(round(&[Value::Int(7)]), Ok(Value::Int(7)));
+        assert_eq!(
+            floor(&[Value::Float(-(2f64.powi(63)))]),
+            Ok(Value::Int(i64::MIN))
+        );
+        assert!(floor(&[Value::Float(1e300)]).is_err());
+        assert!(ceil(&[Value::Float(f64::NEG_INFINITY)]).is_err());
+        assert!(round(&[Value::Float(f64::NAN)]).is_err());
+        assert!(round(&[Value::Float(2f64.powi(63))]).is_err());
+    }
+
+    #[test]
+    fn maps_are_lists_of_pairs() {
+        let map = map_set(&[Value::List(vec![]), Value::from("a"), Value::Int(1)]).unwrap();
+        let map = map_set(&[map, Value::from("a"), Value::Int(2)]).unwrap();
+
+        assert_eq!(
+            map_keys(&[map.clone()]),
+            Ok(Value::List(vec![Value::from("a")]))
+        );
+        assert_eq!(map_get(&[map, Value::from("a")]), Ok(Value::Int(2)));
+        assert!(map_get(&[Value::Int(1), Value::from("a")]).is_err());
+    }
+
+    #[test]
+    fn loads_into_a_runtime() {
+        let mut runtime = Runtime::new(RuntimeConfig::default()).unwrap();
+
+        assert_eq!(runtime.eval("square(4)").unwrap(), Value::Int(16));
+        assert_eq!(runtime.eval("factorial(5)").unwrap(), Value::Int(120));
+        assert_eq!(runtime.eval("upper(\"mag\")").unwrap(), Value::from("MAG"));
+        assert!(runtime.eval("factorial(0 - 1)").is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use magc::lexer::Lexer;
+
+use super::Span;
+
+/// A named piece of Mag source code, like a script file or a single REPL line.
+#[derive(Debug)]
+pub struct Source {
+    /// The name shown in diagnostics, usually a file path or `<repl>`.
+    pub name: String,
+    pub text: String,
+}
+
+impl Source {
+    pub fn new(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            text: text.into(),
+        }
+    }
+}
+
+/// A span within a specific source.
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub source: Arc<Source>,
+    pub span: Span,
+}
+
+impl Location {
+    pub fn new(source: Arc<Source>, span: Span) -> Self {
+        Self { source, span }
+    }
+
+    /// The zero-based line and column of the start of the span.
+    pub fn line_column(&self) -> (usize, usize) {
+        line_column(&self.source.text, self.span.start)
+    }
+
+    /// The zero-based line and column of the end of the span.
+    pub fn end_line_column(&self) -> (usize, usize) {
+        line_column(&self.source.text, self.span.end)
+    }
+}
+
+/// Maps ranges of instruction addresses back to the expressions they were compiled from.
+#[derive(Default)]
+pub struct SourceMap {
+    entries: Vec<(Range<usize>, Location)>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the instructions in the given address range belong to a location.
+    pub fn insert(&mut self, addresses: Range<usize>, location: Location) {
+        if !addresses.is_empty() {
+            self.entries.push((addresses, location));
+        }
+    }
+
+    /// The number of entries recorded so far.
+    pub(super) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Forget every entry recorded after the first `len`, for code which was compiled
+    /// but never reached the machine.
+    pub(super) fn truncate(&mut self, len: usize) {
+        self.entries.truncate(len);
+    }
+
+    /// Find the innermost location containing the instruction at the given address.
+    pub fn lookup(&self, address: usize) -> Option<&Location> {
+        self.entry(address).map(|(_, location)| location)
+    }
+
+    /// Find the address range of the innermost expression containing the given address.
+    pub fn range(&self, address: usize) -> Option<Range<usize>> {
+        self.entry(address).map(|(addresses, _)| addresses.clone())
+    }
+
+    fn entry(&self, address: usize) -> Option<&(Range<usize>, Location)> {
+        self.entries
+            .iter()
+            .filter(|(addresses, _)| addresses.contains(&address))
+            .min_by_key(|(addresses, _)| addresses.len())
+    }
+}
+
+/// The index of the first character of each grapheme in a lexer's source, followed by
+/// the total number of characters, for turning the positions of tokens into character
+/// indices.
+pub(super) fn char_offsets(lexer: &Lexer) -> Vec<usize> {
+    let mut offsets = vec![0];
+
+    for grapheme in &lexer.source {
+        offsets.push(offsets[offsets.len() - 1] + grapheme.chars().count());
+    }
+
+    offsets
+}
+
+/// Turn the grapheme positions of a token or expression into a span of characters,
+/// using the offsets from [`char_offsets`].
+pub(super) fn char_span(offsets: &[usize], start: usize, end: usize) -> Span {
+    let offset = |position: usize| {
+        offsets
+            .get(position)
+            .or(offsets.last())
+            .copied()
+            .unwrap_or(0)
+    };
+
+    Span::new(offset(start), offset(end))
+}
+
+/// Find the zero-based line and column of the character at the given index.
+pub fn line_column(text: &str, index: usize) -> (usize, usize) {
+    let mut line = 0;
+    let mut column = 0;
+
+    for ch in text.chars().take(index) {
+        if ch == '\n' {
+            line += 1;
+            column = 0;
+        } else {
+            column += 1;
+        }
+    }
+
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::{Runtime, RuntimeConfig, RuntimeError};
+
+    fn location(text: &str, start: usize, end: usize) -> Location {
+        Location::new(Arc::new(Source::new("<test>", text)), Span::new(start, end))
+    }
+
+    #[test]
+    fn finds_the_innermost_location() {
+        let mut map = SourceMap::new();
+        map.insert(0..10, location("outer", 0, 5));
+        map.insert(2..4, location("inner", 1, 3));
+        map.insert(6..6, location("empty", 0, 1));
+
+        assert_eq!(map.lookup(3).map(|l| l.source.text.as_str()), Some("inner"));
+        assert_eq!(map.lookup(6).map(|l| l.source.text.as_str()), Some("outer"));
+        assert_eq!(map.range(3), Some(2..4));
+        assert!(map.lookup(10).is_none());
+
+        map.truncate(1);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.range(3), Some(0..10));
+    }
+
+    #[test]
+    fn counts_lines_and_columns_from_zero() {
+        let text = "first\nsecond line\n";
+
+        assert_eq!(line_column(text, 0), (0, 0));
+        assert_eq!(line_column(text, 8), (1, 2));
+        assert_eq!(location(text, 6, 12).end_line_column(), (1, 6));
+    }
+
+    #[test]
+    fn locates_runtime_errors_in_the_source() {
+        let mut runtime = Runtime::new(RuntimeConfig::default()).unwrap();
+        let result = runtime.eval("var items = [1, 2]\n\nget(items, 5)");
+
+        match result {
+            Err(RuntimeError::Native {
+                location: Some(location),
+                ..
+            }) => assert_eq!(location.line_column().0, 2),
+            result => panic!("expected an error with a location, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn counts_graphemes_as_their_characters() {
+        let mut lexer = Lexer::new();
+        lexer.add_text("\"e\u{301}\" x".to_string());
+        let offsets = char_offsets(&lexer);
+
+        assert_eq!(char_span(&offsets, 0, 3), Span::new(0, 4));
+        assert_eq!(char_span(&offsets, 4, 5), Span::new(5, 6));
+        assert_eq!(char_span(&offsets, 4, 9), Span::new(5, 6));
+    }
+
+    #[test]
+    fn points_parser_errors_at_characters() {
+        let mut runtime = Runtime::new(RuntimeConfig {
+            prelude: false,
+            ..RuntimeConfig::default()
+        })
+        .unwrap();
+        let source = "\"caf\u{e9} \u{1f1e9}\u{1f1ea}\" + )";
+
+        match runtime.check_syntax(Source::new("<test>", source)) {
+            Err(RuntimeError::Parser {
+                location: Some(location),
+                ..
+            }) => {
+                assert_eq!(location.span.start, source.chars().count() - 1);
+                assert_eq!(location.line_column(), (0, 12));
+            }
+            result => panic!("expected a parser error, got {:?}", result),
+        }
+    }
+}
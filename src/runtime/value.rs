@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::fmt;
+
+use strontium::machine::register::RegisterValue;
+
+/// A Mag value converted to a Rust-friendly representation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The absence of a value, like the result of a definition.
+    Empty,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    /// A value without a Rust equivalent, holding its printed representation.
+    Other(String),
+}
+
+impl Value {
+    /// The name of the Mag type of this value, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Empty => "Empty",
+            Self::Bool(_) => "Bool",
+            Self::Int(_) => "Int",
+            Self::UInt(_) => "UInt",
+            Self::Float(_) => "Float",
+            Self::String(_) => "String",
+            Self::List(_) => "List",
+            Self::Other(_) => "Object",
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => Ok(()),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+            Self::UInt(value) => write!(f, "{}", value),
+            Self::Float(value) => write!(f, "{:?}", value),
+            Self::String(value) => write!(f, "{}", value),
+            Self::List(values) => {
+                write!(f, "[")?;
+
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+
+                    match value {
+                        Self::String(value) => write!(f, "{}", quote_string(value))?,
+                        value => write!(f, "{}", value)?,
+                    }
+                }
+
+                write!(f, "]")
+            }
+            Self::Other(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Write a string as a Mag string literal, escaping quotes, backslashes and control
+/// characters so that the literal reads back as the same string.
+pub fn quote_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for ch in text.chars() {
+        match ch {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            ch if ch.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", ch as u32)),
+            ch => quoted.push(ch),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+impl From<&RegisterValue> for Value {
+    fn from(value: &RegisterValue) -> Self {
+        match value {
+            RegisterValue::Empty => Self::Empty,
+            RegisterValue::Boolean(value) => Self::Bool(*value),
+            RegisterValue::Int64(value) => Self::Int(*value),
+            RegisterValue::UInt64(value) => Self::UInt(*value),
+            RegisterValue::Float64(value) => Self::Float(*value),
+            RegisterValue::String(value) => Self::String(value.clone()),
+            RegisterValue::Array(values) => Self::List(values.iter().map(Self::from).collect()),
+            value => Self::Other(value.to_string()),
+        }
+    }
+}
+
+impl From<RegisterValue> for Value {
+    fn from(value: RegisterValue) -> Self {
+        Self::from(&value)
+    }
+}
+
+/// Values of types without a Rust equivalent only hold their printed representation,
+/// so they can't be passed back to the machine.
+impl TryFrom<Value> for RegisterValue {
+    type Error = ConversionError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Value::Empty => Self::Empty,
+            Value::Bool(value) => Self::Boolean(value),
+            Value::Int(value) => Self::Int64(value),
+            Value::UInt(value) => Self::UInt64(value),
+            Value::Float(value) => Self::Float64(value),
+            Value::String(value) => Self::String(value),
+            Value::List(values) => Self::Array(
+                values
+                    .into_iter()
+                    .map(Self::try_from)
+                    .collect::<Result<_, _>>()?,
+            ),
+            value @ Value::Other(_) => {
+                return Err(ConversionError {
+                    expected: "Bool, Int, UInt, Float, String or List",
+                    found: value.type_name(),
+                })
+            }
+        })
+    }
+}
+
+/// An error raised when a `Value` can't be converted into the requested Rust type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected a value of type {}, found {}",
+            self.expected, self.found
+        )
+    }
+}
+
+impl Error for ConversionError {}
+
+macro_rules! impl_conversions {
+    ($($rust:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$rust> for Value {
+                fn from(value: $rust) -> Self {
+                    Self::$variant(value)
+                }
+            }
+
+            impl TryFrom<Value> for $rust {
+                type Error = ConversionError;
+
+                fn try_from(value: Value) -> Result<Self, Self::Error> {
+                    match value {
+                        Value::$variant(value) => Ok(value),
+                        value => Err(ConversionError {
+                            expected: stringify!($variant),
+                            found: value.type_name(),
+                        }),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_conversions! {
+    bool => Bool,
+    i64 => Int,
+    u64 => UInt,
+    f64 => Float,
+    String => String,
+    Vec<Value> => List,
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Self::Empty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_and_from_rust_types() {
+        assert_eq!(Value::from(3i64), Value::Int(3));
+        assert_eq!(Value::from("mag"), Value::String("mag".to_string()));
+        assert_eq!(Value::from(()), Value::Empty);
+        assert_eq!(i64::try_from(Value::Int(3)), Ok(3));
+        assert_eq!(
+            bool::try_from(Value::Int(3)),
+            Err(ConversionError {
+                expected: "Bool",
+                found: "Int"
+            })
+        );
+    }
+
+    #[test]
+    fn quotes_strings_with_escapes() {
+        assert_eq!(quote_string("plain"), "\"plain\"");
+        assert_eq!(quote_string("a \"b\" \\ c"), "\"a \\\"b\\\" \\\\ c\"");
+        assert_eq!(quote_string("tab\tline\n\u{7}"), "\"tab\\tline\\n\\u{7}\"");
+    }
+
+    #[test]
+    fn prints_strings_in_lists_as_literals() {
+        let value = Value::List(vec![Value::from("say \"hi\"\n"), Value::Int(1)]);
+
+        assert_eq!(value.to_string(), "[\"say \\\"hi\\\"\\n\", 1]");
+        assert_eq!(Value::from("say \"hi\"").to_string(), "say \"hi\"");
+    }
+
+    #[test]
+    fn round_trips_through_registers() {
+        let value = Value::List(vec![Value::Bool(true), Value::Float(1.5), Value::from("a")]);
+        let register = RegisterValue::try_from(value.clone()).unwrap();
+
+        assert_eq!(Value::from(register), value);
+    }
+
+    #[test]
+    fn keeps_values_without_registers_out_of_the_machine() {
+        let value = Value::List(vec![Value::Other("{}".to_string())]);
+
+        assert_eq!(
+            RegisterValue::try_from(value)
+                .err()
+                .map(|error| error.found),
+            Some("Object")
+        );
+    }
+}